@@ -0,0 +1,73 @@
+//! Bulk-load throughput of `DbLoader::load_dataframe` on a 200k-row sheet,
+//! with the value dictionary and full-text index built afterwards by
+//! `DbLoader::build_lookups` timed separately.
+//!
+//! Run with `cargo bench --bench load_dataframe`.
+
+use std::time::{Duration, Instant};
+use polars::prelude::*;
+use sheet_services::services::db_loader::DbLoader;
+
+const ROWS: usize = 200_000;
+const RUNS: usize = 3;
+
+/// A sheet shaped like a typical sales export: ids, amounts, dates as text,
+/// a low-cardinality category and a sparse free-text column.
+fn sample_dataframe() -> DataFrame {
+    let states = ["SP", "RJ", "MG", "RS", "PR", "BA"];
+    df!(
+        "id" => (0..ROWS as i64).collect::<Vec<_>>(),
+        "quantidade" => (0..ROWS).map(|i| (i % 17) as f64).collect::<Vec<_>>(),
+        "preco_unitario" => (0..ROWS).map(|i| Some(i as f64 * 0.37).filter(|_| i % 11 != 0)).collect::<Vec<_>>(),
+        "estado" => (0..ROWS).map(|i| states[i % states.len()]).collect::<Vec<_>>(),
+        "cliente" => (0..ROWS).map(|i| format!("Cliente {}", i % 5000)).collect::<Vec<_>>(),
+        "data_pedido" => (0..ROWS).map(|i| format!("2024-{:02}-{:02}", i % 12 + 1, i % 28 + 1)).collect::<Vec<_>>(),
+        "observacao" => (0..ROWS).map(|i| (i % 7 == 0).then(|| format!("Entrega {} atrasada", i))).collect::<Vec<_>>()
+    )
+    .expect("valid benchmark dataframe")
+}
+
+#[tokio::main]
+async fn main() {
+    let df = sample_dataframe();
+    let cells = df.height() * df.width();
+
+    let mut best = Duration::MAX;
+    let mut best_lookups = Duration::MAX;
+    for run in 1..=RUNS {
+        let db_loader = DbLoader::new().await.expect("in-memory database");
+        let start = Instant::now();
+        db_loader
+            .load_dataframe(df.clone(), "bench_sheet")
+            .await
+            .expect("load succeeds");
+        let elapsed = start.elapsed();
+
+        let lookups_start = Instant::now();
+        db_loader
+            .build_lookups("bench_sheet")
+            .await
+            .expect("lookups build");
+        let lookups = lookups_start.elapsed();
+
+        println!(
+            "run {}: {:?} ({:.0} rows/sec), lookups {:?}",
+            run,
+            elapsed,
+            ROWS as f64 / elapsed.as_secs_f64(),
+            lookups
+        );
+        best = best.min(elapsed);
+        best_lookups = best_lookups.min(lookups);
+    }
+
+    println!(
+        "load_dataframe: {} rows x {} columns ({} cells), best {:?} = {:.0} rows/sec",
+        ROWS,
+        df.width(),
+        cells,
+        best,
+        ROWS as f64 / best.as_secs_f64()
+    );
+    println!("build_lookups: best {:?}", best_lookups);
+}
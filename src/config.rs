@@ -1,11 +1,45 @@
 use serde::Deserialize;
 use anyhow::Result;
 use dotenvy::dotenv;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub max_file_size: usize,
     pub openai_key: String,
+    /// Approximate number of prompt tokens the schema description may use.
+    pub schema_token_budget: usize,
+    /// Threads in the pool that decodes and profiles workbooks.
+    pub cpu_workers: usize,
+    /// Jobs allowed to wait for a CPU worker before requests get a 503.
+    pub cpu_queue_depth: usize,
+    /// Wall-clock limit for each generated query.
+    pub query_timeout_ms: u64,
+    /// Rows returned per generated query; the rest are only counted.
+    pub max_result_rows: usize,
+    /// Idle time after which a session and its database are dropped.
+    pub session_idle_secs: u64,
+    /// Directory for session database files; in memory when unset.
+    pub session_dir: Option<PathBuf>,
+    /// Build FTS5 indexes over long text columns when loading sheets.
+    pub full_text_search: bool,
+    /// Indexes built per loaded table, at most.
+    pub index_max_per_table: usize,
+    /// Time allowed for choosing and building one table's indexes.
+    pub index_time_budget_ms: u64,
+    /// Time allowed for inferring keys and relationships after each file load.
+    pub relationship_time_budget_ms: u64,
+    /// How long downloaded files, parsed workbooks and their analyses are kept.
+    pub retention_files_secs: u64,
+    /// How long a session's database is kept after it was created, however
+    /// active the session is.
+    pub retention_sessions_secs: u64,
+    /// How long stored query results can be paged after they ran.
+    pub retention_results_secs: u64,
+    /// Time between runs of the background purger.
+    pub purge_interval_secs: u64,
+    /// Bearer token for the erasure endpoint; the endpoint is disabled when unset.
+    pub erasure_api_token: Option<String>,
 }
 
 impl Config {
@@ -17,10 +51,56 @@ impl Config {
         let openai_key = std::env::var("OPENAI_API_KEY")
             .map_err(|e| anyhow::anyhow!("Failed to load OPENAI_API_KEY: {}", e))?;
 
+        let schema_token_budget = env_or("SCHEMA_TOKEN_BUDGET", 4000)?;
+        let default_workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let cpu_workers = env_or("CPU_WORKERS", default_workers)?.max(1);
+        let cpu_queue_depth = env_or("CPU_QUEUE_DEPTH", 2 * cpu_workers)?;
+        let query_timeout_ms = env_or("QUERY_TIMEOUT_MS", 10_000)?;
+        let max_result_rows = env_or("MAX_RESULT_ROWS", 1000)?;
+        let session_idle_secs = env_or("SESSION_IDLE_SECS", 3600)?;
+        let session_dir = std::env::var("SESSION_DIR").ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        let full_text_search = env_or("FULL_TEXT_SEARCH", true)?;
+        let index_max_per_table = env_or("INDEX_MAX_PER_TABLE", 4)?;
+        let index_time_budget_ms = env_or("INDEX_TIME_BUDGET_MS", 2000)?;
+        let relationship_time_budget_ms = env_or("RELATIONSHIP_TIME_BUDGET_MS", 2000)?;
+        let retention_files_secs = env_or("RETENTION_FILES_SECS", 3600)?;
+        let retention_sessions_secs = env_or("RETENTION_SESSIONS_SECS", 86_400)?;
+        let retention_results_secs = env_or("RETENTION_RESULTS_SECS", 86_400)?;
+        let purge_interval_secs = env_or("PURGE_INTERVAL_SECS", 300)?.max(1);
+        let erasure_api_token = std::env::var("ERASURE_API_TOKEN").ok()
+            .filter(|token| !token.is_empty());
+
         Ok(Config {
             max_file_size: 10 * 1024 * 1024, // 10MB
             openai_key,
+            schema_token_budget,
+            cpu_workers,
+            cpu_queue_depth,
+            query_timeout_ms,
+            max_result_rows,
+            session_idle_secs,
+            session_dir,
+            full_text_search,
+            index_max_per_table,
+            index_time_budget_ms,
+            relationship_time_budget_ms,
+            retention_files_secs,
+            retention_sessions_secs,
+            retention_results_secs,
+            purge_interval_secs,
+            erasure_api_token,
         })
     }
 }
 
+/// Reads an optional numeric setting from the environment.
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> Result<T> {
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid value for {}: {}", key, value)),
+        Err(_) => Ok(default),
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
     response::{IntoResponse, Response},
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
 };
 use serde_json::json;
 use axum::Json;
@@ -8,34 +8,36 @@ use axum::Json;
 #[derive(Debug)]
 pub enum AppError {
     InvalidInput(String),
+    NotFound(String),
+    Unauthorized(String),
+    /// The caller is known but may not touch this resource.
+    Forbidden(String),
     IoError(std::io::Error),
     LlmError(String),
     ParseError(String),
     DatabaseError(String),
     Internal(String),
-    OpenAI(String),
-    AwsS3(String),
-    Database(String),
-    HttpError(String),
     FileProcessingError(String),
     DataFrameError(String),
+    /// The CPU pool is saturated; the client should retry after this many seconds.
+    Overloaded { retry_after: u64 },
 }
 
 impl std::fmt::Display for AppError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            AppError::HttpError(msg) => write!(f, "HTTP Error: {}", msg),
             AppError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            AppError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            AppError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            AppError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
             AppError::IoError(err) => write!(f, "IO error: {}", err),
             AppError::LlmError(msg) => write!(f, "LLM error: {}", msg),
             AppError::ParseError(msg) => write!(f, "Parse error: {}", msg),
             AppError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
-            AppError::OpenAI(msg) => write!(f, "OpenAI error: {}", msg),
-            AppError::AwsS3(msg) => write!(f, "AWS S3 error: {}", msg),
-            AppError::Database(msg) => write!(f, "Database error: {}", msg),
             AppError::FileProcessingError(msg) => write!(f, "File processing error: {}", msg),
             AppError::DataFrameError(msg) => write!(f, "DataFrame error: {}", msg),
+            AppError::Overloaded { retry_after } => write!(f, "Server busy, retry after {} seconds", retry_after),
         }
     }
 }
@@ -68,25 +70,37 @@ impl From<tokio_rusqlite::Error> for AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let retry_after = match &self {
+            AppError::Overloaded { retry_after } => Some(*retry_after),
+            _ => None,
+        };
+
         let (status, message) = match self {
-            AppError::HttpError(msg) =>(StatusCode::BAD_REQUEST, msg),
             AppError::InvalidInput(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
             AppError::IoError(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
             AppError::LlmError(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             AppError::ParseError(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::DatabaseError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            AppError::OpenAI(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
-            AppError::AwsS3(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
-            AppError::Database(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::FileProcessingError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::DataFrameError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+            AppError::Overloaded { retry_after } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Server busy, retry after {} seconds", retry_after),
+            ),
         };
 
         let body = Json(json!({
             "error": message
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(seconds) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
\ No newline at end of file
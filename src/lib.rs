@@ -0,0 +1,30 @@
+use crate::config::Config;
+use crate::error::AppError;
+use crate::services::cpu_pool::CpuPool;
+use crate::services::retention::{DataRetention, RetentionPolicy};
+use crate::services::session::SessionStore;
+
+pub mod config;
+pub mod error;
+pub mod logging;
+pub mod models;
+pub mod routes;
+pub mod services;
+
+// Application state
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Config,
+    pub cpu_pool: CpuPool,
+    pub sessions: SessionStore,
+    pub retention: DataRetention,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Result<Self, AppError> {
+        let cpu_pool = CpuPool::new(config.cpu_workers, config.cpu_queue_depth)?;
+        let sessions = SessionStore::new(&config);
+        let retention = DataRetention::new(RetentionPolicy::from_config(&config), sessions.clone());
+        Ok(Self { config, cpu_pool, sessions, retention })
+    }
+}
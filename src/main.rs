@@ -2,13 +2,7 @@ use anyhow::Result;
 use axum::Router;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use crate::config::Config;
-mod config;
-mod error;
-mod logging;
-mod routes;
-mod services;
-pub mod models;
+use sheet_services::{config::Config, logging, routes, AppState};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,7 +13,11 @@ async fn main() -> Result<()> {
     let config = Config::new()?;
     
     // Create app state
-    let state = Arc::new(AppState::new(config));
+    let state = Arc::new(AppState::new(config)?);
+
+    // Delete user data past its retention period
+    let purge_interval = std::time::Duration::from_secs(state.config.purge_interval_secs);
+    state.retention.clone().spawn_purger(purge_interval);
     
     // Build our application with a route
     let app = Router::new()
@@ -35,15 +33,3 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
-
-// Application state
-#[derive(Clone)]
-pub struct AppState {
-    pub config: Config,
-}
-
-impl AppState {
-    pub fn new(config: Config) -> Self {
-        Self { config }
-    }
-}
@@ -0,0 +1,70 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    routing::post,
+    Router,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use crate::{
+    AppState,
+    error::AppError,
+    services::retention::DeletionReport,
+};
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/privacy/erasure", post(erase_user_data))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErasureRequest {
+    user_email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErasureResponse {
+    user_email: String,
+    erased_at: String,
+    #[serde(flatten)]
+    deleted: DeletionReport,
+}
+
+/// Deletes a user's sessions, query results and cached files. Files other
+/// users also use stay cached.
+/// Requires `Authorization: Bearer <ERASURE_API_TOKEN>`.
+async fn erase_user_data(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<ErasureRequest>,
+) -> Result<Json<ErasureResponse>, AppError> {
+    authorize(&headers, state.config.erasure_api_token.as_deref())?;
+    if request.user_email.trim().is_empty() {
+        return Err(AppError::InvalidInput("user_email is required".to_string()));
+    }
+
+    let deleted = state.retention.erase_user(&request.user_email).await;
+    Ok(Json(ErasureResponse {
+        user_email: request.user_email,
+        erased_at: chrono::Utc::now().to_rfc3339(),
+        deleted,
+    }))
+}
+
+/// Checks the bearer token. Both sides are hashed first so the comparison
+/// takes the same time wherever they differ.
+fn authorize(headers: &HeaderMap, expected: Option<&str>) -> Result<(), AppError> {
+    let expected = expected
+        .ok_or_else(|| AppError::Unauthorized("Erasure is not enabled on this server".to_string()))?;
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError::Unauthorized("Missing bearer token".to_string()))?;
+
+    if Sha256::digest(token.as_bytes()) != Sha256::digest(expected.as_bytes()) {
+        return Err(AppError::Unauthorized("Invalid bearer token".to_string()));
+    }
+    Ok(())
+}
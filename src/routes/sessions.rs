@@ -0,0 +1,358 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::{delete, get, post},
+    Router,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::{
+    AppState,
+    error::AppError,
+    routes::sheets::{load_file, FileInfo, FileLoad},
+    services::{
+        db_loader::{CatalogEntry, ColumnMapping, Relationship},
+        excel::CleaningStep,
+        file_processor,
+        query_runner::{QueryRunner, ResultColumn, SortKey, MAX_STORED_RESULT_ROWS},
+        session::Session,
+    },
+};
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// Identifies the caller on requests for an existing session, which only
+/// its owner may use.
+const USER_EMAIL_HEADER: &str = "x-user-email";
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/sessions", post(create_session))
+        .route("/sessions/:session_id", get(get_session).delete(delete_session))
+        .route("/sessions/:session_id/files", post(attach_files))
+        .route("/sessions/:session_id/files/:file_name", delete(detach_file))
+        .route("/sessions/:session_id/schema", get(get_schema))
+        .route("/sessions/:session_id/results/:result_id", get(get_result_page))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSessionRequest {
+    /// Reuses the chat's session when there is one; a new id is generated
+    /// otherwise.
+    #[serde(default)]
+    chat_id: Option<String>,
+    /// Owner of the session: the only user who may use it, and whose erasure
+    /// request removes it.
+    user_email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttachRequest {
+    files: Vec<FileInfo>,
+    /// Ordered cleaning steps to apply to every sheet; defaults to `CleaningPipeline::default()`.
+    #[serde(default)]
+    cleaning: Option<Vec<CleaningStep>>,
+}
+
+/// A file loaded into a session and the tables its sheets became.
+#[derive(Debug, Serialize)]
+pub struct SessionFile {
+    file_name: String,
+    tables: Vec<String>,
+    row_count: usize,
+}
+
+/// A loaded table and the headers its columns came from.
+#[derive(Debug, Serialize)]
+pub struct SessionTable {
+    #[serde(flatten)]
+    entry: CatalogEntry,
+    columns: Vec<ColumnMapping>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    session_id: String,
+    files: Vec<SessionFile>,
+    tables: Vec<SessionTable>,
+}
+
+/// A file that could not be attached, and why.
+#[derive(Debug, Serialize)]
+pub struct FailedFile {
+    file_name: String,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttachResponse {
+    loaded: Vec<FileLoad>,
+    failed: Vec<FailedFile>,
+    session: SessionInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetachResponse {
+    removed_tables: Vec<String>,
+    /// Stored results that read the removed tables, which can no longer be paged.
+    removed_results: usize,
+    session: SessionInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchemaResponse {
+    session_id: String,
+    tables: Vec<SessionTable>,
+    relationships: Vec<Relationship>,
+    /// The schema description the LLM is given.
+    schema: String,
+}
+
+async fn create_session(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CreateSessionRequest>,
+) -> Result<(StatusCode, Json<SessionInfo>), AppError> {
+    let user_email = request.user_email.trim();
+    if user_email.is_empty() {
+        return Err(AppError::InvalidInput("user_email is required".to_string()));
+    }
+    let (session, created) = match request.chat_id {
+        Some(chat_id) => state.sessions.resume_or_create(&chat_id, user_email).await?,
+        None => (state.sessions.create(user_email).await?, true),
+    };
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(session_info(&session).await?)))
+}
+
+async fn get_session(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<SessionInfo>, AppError> {
+    let session = find_session(&state, &session_id, &headers)?;
+    Ok(Json(session_info(&session).await?))
+}
+
+async fn delete_session(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    find_session(&state, &session_id, &headers)?;
+    state.sessions.remove(&session_id)
+        .ok_or_else(|| AppError::NotFound(format!("Session {} not found", session_id)))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Loads each file into the session; files already loaded with the same
+/// contents are left as they are. A file that fails is reported and the
+/// others are still loaded.
+async fn attach_files(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<AttachRequest>,
+) -> Result<Json<AttachResponse>, AppError> {
+    let user_email = caller_email(&headers)?;
+    let session = state.sessions.get(&session_id, user_email)?;
+    if request.files.is_empty() {
+        return Err(AppError::InvalidInput("No file provided".to_string()));
+    }
+
+    let mut loaded = Vec::with_capacity(request.files.len());
+    let mut failed = Vec::new();
+    for file_info in &request.files {
+        match load_file(&state, &session, user_email, file_info, request.cleaning.clone()).await {
+            Ok(file_load) => loaded.push(file_load),
+            Err(e) => {
+                let file_name = file_info.name.clone()
+                    .unwrap_or_else(|| file_processor::file_name_from_url(&file_info.signed_url));
+                tracing::warn!("Failed to attach {} to session {}: {}", file_name, session_id, e);
+                failed.push(FailedFile { file_name, error: e.to_string() });
+            }
+        }
+    }
+    Ok(Json(AttachResponse {
+        loaded,
+        failed,
+        session: session_info(&session).await?,
+    }))
+}
+
+/// Removes a file's tables from the session, e.g. one uploaded by mistake.
+async fn detach_file(
+    State(state): State<Arc<AppState>>,
+    Path((session_id, file_name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<DetachResponse>, AppError> {
+    let session = find_session(&state, &session_id, &headers)?;
+    let _loading = session.lock_loading().await;
+    if !session.has_file(&file_name).await? {
+        return Err(AppError::NotFound(format!("File {} not found in session {}", file_name, session_id)));
+    }
+
+    let removed_tables = session.db_loader.remove_file(&file_name).await?;
+    session.remove_loaded_file(&file_name);
+    let removed_results = session.purge_invalid_results().await?;
+    tracing::info!("Removed file {} ({} tables) from session {}", file_name, removed_tables.len(), session_id);
+    Ok(Json(DetachResponse {
+        removed_tables,
+        removed_results,
+        session: session_info(&session).await?,
+    }))
+}
+
+async fn get_schema(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<SchemaResponse>, AppError> {
+    let session = find_session(&state, &session_id, &headers)?;
+    Ok(Json(SchemaResponse {
+        session_id,
+        tables: session_tables(&session).await?,
+        relationships: session.db_loader.get_relationships().await?,
+        schema: session.db_loader.get_schema_with_samples(state.config.schema_token_budget).await?,
+    }))
+}
+
+/// The session, if it belongs to the user named in the `X-User-Email` header.
+fn find_session(state: &AppState, session_id: &str, headers: &HeaderMap) -> Result<Arc<Session>, AppError> {
+    state.sessions.get(session_id, caller_email(headers)?)
+}
+
+fn caller_email(headers: &HeaderMap) -> Result<&str, AppError> {
+    headers.get(USER_EMAIL_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| AppError::Unauthorized("Missing X-User-Email header".to_string()))
+}
+
+/// The session's catalog, with each table's column mappings.
+async fn session_tables(session: &Session) -> Result<Vec<SessionTable>, AppError> {
+    let mut tables = Vec::new();
+    for entry in session.db_loader.get_catalog().await? {
+        let columns = session.db_loader.column_mappings(&entry.table_name).await?;
+        tables.push(SessionTable { entry, columns });
+    }
+    Ok(tables)
+}
+
+/// The session's catalog, with its tables grouped by file.
+async fn session_info(session: &Session) -> Result<SessionInfo, AppError> {
+    let tables = session_tables(session).await?;
+    let mut files: BTreeMap<&str, SessionFile> = BTreeMap::new();
+    for SessionTable { entry, .. } in &tables {
+        let file = files.entry(entry.file_name.as_str()).or_insert_with(|| SessionFile {
+            file_name: entry.file_name.clone(),
+            tables: Vec::new(),
+            row_count: 0,
+        });
+        file.tables.push(entry.table_name.clone());
+        file.row_count += entry.row_count;
+    }
+    Ok(SessionInfo {
+        session_id: session.id.clone(),
+        files: files.into_values().collect(),
+        tables,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+    /// Comma-separated result columns; a leading `-` sorts descending.
+    sort: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResultPage {
+    result_id: String,
+    offset: usize,
+    limit: usize,
+    sort: Option<String>,
+    columns: Vec<ResultColumn>,
+    rows: Vec<Vec<JsonValue>>,
+    row_count: usize,
+    /// Rows in the whole result, when known.
+    total_row_count: Option<usize>,
+    /// Rows that can be paged through. Results longer than
+    /// `MAX_STORED_RESULT_ROWS` are cut off there.
+    stored_row_count: usize,
+    has_more: bool,
+    execution_ms: f64,
+}
+
+/// Pages through a stored query result without involving the LLM. The
+/// result's rows are saved on the first request, so every page comes from
+/// the same ordered rows.
+async fn get_result_page(
+    State(state): State<Arc<AppState>>,
+    Path((session_id, result_id)): Path<(String, String)>,
+    Query(params): Query<PageParams>,
+    headers: HeaderMap,
+) -> Result<Json<ResultPage>, AppError> {
+    let session = find_session(&state, &session_id, &headers)?;
+    let result = session.result(&result_id)
+        .ok_or_else(|| AppError::NotFound(format!("Result {} not found", result_id)))?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > state.config.max_result_rows {
+        return Err(AppError::InvalidInput(format!(
+            "limit must be between 1 and {}",
+            state.config.max_result_rows
+        )));
+    }
+    if params.offset > MAX_STORED_RESULT_ROWS {
+        return Err(AppError::InvalidInput(format!(
+            "offset must be at most {}",
+            MAX_STORED_RESULT_ROWS
+        )));
+    }
+    let sort = params.sort.as_deref()
+        .map(|sort| sort_keys(sort, &result.columns))
+        .transpose()?
+        .unwrap_or_default();
+
+    let runner = QueryRunner::new(session.db_loader.clone())
+        .with_limits(Duration::from_millis(state.config.query_timeout_ms), state.config.max_result_rows);
+    let page = runner.page(&result.table, &result.sql, &sort, params.offset, limit).await?;
+
+    Ok(Json(ResultPage {
+        result_id,
+        offset: params.offset,
+        limit,
+        sort: params.sort,
+        columns: result.columns,
+        row_count: page.rows.len(),
+        has_more: params.offset + page.rows.len() < page.stored_row_count,
+        rows: page.rows,
+        total_row_count: result.total_row_count,
+        stored_row_count: page.stored_row_count,
+        execution_ms: page.execution_ms,
+    }))
+}
+
+/// Parses `sort`, accepting only the result's own columns.
+fn sort_keys(sort: &str, columns: &[ResultColumn]) -> Result<Vec<SortKey>, AppError> {
+    sort.split(',')
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .map(|term| {
+            let (name, descending) = match term.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (term.strip_prefix('+').unwrap_or(term), false),
+            };
+            let column = columns.iter()
+                .position(|c| c.name == name)
+                .ok_or_else(|| AppError::InvalidInput(format!("Cannot sort by unknown column {}", name)))?;
+            Ok(SortKey { column, descending })
+        })
+        .collect()
+}
@@ -11,9 +11,11 @@ use crate::{
     AppState, 
     error::AppError, 
     services::{
-        file_processor,
-        db_loader::DbLoader,
-        llm_agent::{LlmAgent, QueryResult}
+        file_processor::{self, FileFetch},
+        db_loader::CatalogEntry,
+        excel::{CleaningPipeline, CleaningStep, types::ProcessingReport},
+        llm_agent::{LlmAgent, QueryResult},
+        session::Session,
     }
 };
 use tower_http::cors::{CorsLayer, Any};
@@ -33,8 +35,11 @@ pub fn routes() -> Router<Arc<AppState>> {
 #[derive(Debug, Deserialize)]
 pub struct FileInfo {
     #[serde(rename = "type")]
-    file_type: String,
-    signed_url: String,
+    pub file_type: String,
+    pub signed_url: String,
+    /// Original file name; falls back to the last segment of the URL path.
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -43,6 +48,9 @@ pub struct AnalyzeRequest {
     chat_id: String,
     messages: Vec<String>,
     files: Vec<FileInfo>,
+    /// Ordered cleaning steps to apply to every sheet; defaults to `CleaningPipeline::default()`.
+    #[serde(default)]
+    cleaning: Option<Vec<CleaningStep>>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -71,8 +79,15 @@ pub struct AnalyzeResponse {
 
 #[derive(Debug, Serialize)]
 pub struct FullAnalysisResponse {
-    analysis: AnalyzeResponse,
+    /// Session holding the loaded data, which is the chat id; query results
+    /// can be paged under it.
+    session_id: String,
+    /// Workbook analysis and load report; `None` when the file was already
+    /// loaded earlier in the chat.
+    analysis: Option<AnalyzeResponse>,
     tool_result: QueryResult,
+    processing_report: Option<ProcessingReport>,
+    catalog: Vec<CatalogEntry>,
     new_file_url: Option<String>,
 }
 
@@ -88,10 +103,62 @@ async fn analyze_sheet(
         request.chat_id
     );
 
-    // 1. Validate file type and get URL
+    // 1. Find the chat's session; a file it already holds is not loaded again
     let file_info = request.files.first()
         .ok_or_else(|| AppError::InvalidInput("No file provided".to_string()))?;
+    let session = state.sessions.get_or_create(&request.chat_id, &request.user_email).await?;
+    let db_loader = session.db_loader.clone();
+    let file_load = load_file(&state, &session, &request.user_email, file_info, request.cleaning).await?;
+
+    // 2. Generate LLM analysis
+    tracing::info!("Starting LLM analysis...");
+    let llm_start = std::time::Instant::now();
+    let catalog = db_loader.get_catalog().await?;
+    let llm_agent = LlmAgent::new_with_loader(&state.config.openai_key, db_loader)?
+        .with_schema_token_budget(state.config.schema_token_budget)
+        .with_query_limits(
+            std::time::Duration::from_millis(state.config.query_timeout_ms),
+            state.config.max_result_rows,
+        );
+    let agent_response = llm_agent.generate_analysis(&request.messages).await?;
+    let query_result = llm_agent.execute_queries(agent_response).await?;
+    session.store_results(&query_result.results);
+    tracing::info!("LLM analysis completed in {:?}", llm_start.elapsed());
     
+    tracing::info!("Total processing completed in {:?}", start.elapsed());
+
+    Ok(Json(FullAnalysisResponse {
+        session_id: session.id.clone(),
+        analysis: file_load.analysis,
+        tool_result: query_result,
+        processing_report: file_load.processing_report,
+        catalog,
+        new_file_url: None,
+    }))
+}
+
+/// What attaching one file to a session did.
+#[derive(Debug, Serialize)]
+pub struct FileLoad {
+    pub file_name: String,
+    /// Set when the file was already loaded with the same contents, so
+    /// nothing was done.
+    pub already_loaded: bool,
+    pub analysis: Option<AnalyzeResponse>,
+    pub processing_report: Option<ProcessingReport>,
+}
+
+/// Downloads, analyzes and loads an XLSX file into the session for
+/// `user_email`, unless the same contents are already loaded there under
+/// this name. New contents under a loaded name replace the old file's
+/// tables.
+pub(crate) async fn load_file(
+    state: &AppState,
+    session: &Session,
+    user_email: &str,
+    file_info: &FileInfo,
+    cleaning: Option<Vec<CleaningStep>>,
+) -> Result<FileLoad, AppError> {
     tracing::info!(
         "Processing file type: {}, URL length: {}", 
         file_info.file_type,
@@ -103,19 +170,41 @@ async fn analyze_sheet(
         return Err(AppError::InvalidInput("Only XLSX files are supported".to_string()));
     }
 
-    // 2. Download file and create DB loader concurrently
-    let (file_data, db_loader) = tokio::join!(
-        file_processor::load_file_from_url(&file_info.signed_url),
-        DbLoader::new()
-    );
-    
-    let file_data = file_data?;
-    let db_loader = db_loader?;
-    
-    // 3. Analyze Excel file structure using the downloaded data
+    let file_name = file_info.name.clone()
+        .unwrap_or_else(|| file_processor::file_name_from_url(&file_info.signed_url));
+    let _loading = session.lock_loading().await;
+
+    // A loaded file is revalidated with a conditional GET and only downloaded
+    // again when it changed. Downloaded contents are compared too, so a file
+    // re-uploaded under the same name is loaded again
+    let loaded = session.loaded_file(&file_name);
+    let file_data = match file_processor::load_file_from_url(&state.cpu_pool, &file_info.signed_url, loaded.as_ref()).await? {
+        FileFetch::Fetched(file_data) => file_data,
+        FileFetch::Unchanged(version) => {
+            state.retention.record_file(user_email, &version.hash);
+            tracing::info!("File {} already loaded in session {}", file_name, session.id);
+            return Ok(FileLoad { file_name, already_loaded: true, analysis: None, processing_report: None });
+        }
+    };
+    state.retention.record_file(user_email, &file_data.hash);
+    if loaded.is_some_and(|loaded| loaded.hash == file_data.hash) {
+        tracing::info!("File {} already loaded in session {}", file_name, session.id);
+        // Keep the latest ETag for the next revalidation
+        session.set_loaded_file(&file_name, file_data.version());
+        return Ok(FileLoad { file_name, already_loaded: true, analysis: None, processing_report: None });
+    }
+    if session.has_file(&file_name).await? {
+        tracing::info!("File {} changed since it was loaded in session {}, replacing it", file_name, session.id);
+        session.db_loader.remove_file(&file_name).await?;
+        session.remove_loaded_file(&file_name);
+        session.purge_invalid_results().await?;
+    }
+
+    // Parse the workbook once, then analyze its structure
     tracing::info!("Starting Excel file analysis...");
     let analysis_start = std::time::Instant::now();
-    let analysis = file_processor::analyze_excel_file_from_bytes(file_data.clone()).await?;
+    let workbook = file_processor::parse_workbook(&state.cpu_pool, &file_data).await?;
+    let analysis = file_processor::analyze_workbook(&state.cpu_pool, &file_data, workbook.clone()).await?;
     tracing::info!(
         "Excel analysis completed in {:?}. Found {} sheets, {} rows, {} columns",
         analysis_start.elapsed(),
@@ -123,25 +212,22 @@ async fn analyze_sheet(
         analysis.row_count,
         analysis.column_count
     );
-    
-    // 4. Process Excel file and load into database
+
+    // Process Excel file and load into database
     tracing::info!("Loading data into database...");
     let db_load_start = std::time::Instant::now();
-    let tables_created = file_processor::process_excel_file(file_data, &db_loader).await?;
-    tracing::info!("Created {} tables in database in {:?}", tables_created, db_load_start.elapsed());
-    
-    // 5. Generate LLM analysis
-    tracing::info!("Starting LLM analysis...");
-    let llm_start = std::time::Instant::now();
-    let llm_agent = LlmAgent::new_with_loader(&state.config.openai_key, db_loader)?;
-    let agent_response = llm_agent.generate_analysis(&request.messages).await?;
-    let query_result = llm_agent.execute_queries(agent_response).await?;
-    tracing::info!("LLM analysis completed in {:?}", llm_start.elapsed());
-    
-    tracing::info!("Total processing completed in {:?}", start.elapsed());
+    let cleaning = cleaning
+        .map(CleaningPipeline::new)
+        .unwrap_or_default();
+    let processing_report = file_processor::process_excel_file(&state.cpu_pool, workbook, &file_name, &session.db_loader, cleaning).await?;
+    session.set_loaded_file(&file_name, file_data.version());
+    tracing::info!("Created {} tables in database in {:?}", processing_report.tables_created, db_load_start.elapsed());
 
-    Ok(Json(FullAnalysisResponse {
-        analysis: AnalyzeResponse {
+    Ok(FileLoad {
+        file_name,
+        already_loaded: false,
+        processing_report: Some(processing_report),
+        analysis: Some(AnalyzeResponse {
             sheet_names: analysis.sheet_names,
             row_count: analysis.row_count,
             column_count: analysis.column_count,
@@ -161,8 +247,6 @@ async fn analyze_sheet(
             date_columns: analysis.date_columns,
             numeric_columns: analysis.numeric_columns,
             text_columns: analysis.text_columns,
-        },
-        tool_result: query_result,
-        new_file_url: None,
-    }))
+        }),
+    })
 }
\ No newline at end of file
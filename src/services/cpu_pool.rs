@@ -0,0 +1,66 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use tokio::sync::{oneshot, Semaphore};
+use crate::error::AppError;
+
+/// Seconds clients are asked to wait when the pool is saturated.
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// Dedicated, size-limited pool for CPU-heavy work (workbook decoding,
+/// profiling, dataframe construction), kept off the Tokio workers.
+///
+/// At most `workers` jobs run at once and at most `queue_depth` more wait for
+/// a thread; anything beyond that is rejected with `AppError::Overloaded`
+/// instead of queueing without bound. Rayon parallelism inside a job stays on
+/// this pool.
+#[derive(Clone)]
+pub struct CpuPool {
+    pool: Arc<rayon::ThreadPool>,
+    permits: Arc<Semaphore>,
+}
+
+impl CpuPool {
+    pub fn new(workers: usize, queue_depth: usize) -> Result<Self, AppError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(workers)
+            .thread_name(|idx| format!("cpu-pool-{}", idx))
+            .build()
+            .map_err(|e| AppError::Internal(format!("Failed to build CPU pool: {}", e)))?;
+
+        tracing::info!("CPU pool started with {} workers, queue depth {}", workers, queue_depth);
+        Ok(Self {
+            pool: Arc::new(pool),
+            permits: Arc::new(Semaphore::new(workers + queue_depth)),
+        })
+    }
+
+    /// Runs `job` on the pool, or fails fast if the pool and its queue are full.
+    pub async fn run<F, T>(&self, job: F) -> Result<T, AppError>
+    where
+        F: FnOnce() -> Result<T, AppError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self.permits.clone().try_acquire_owned().map_err(|_| {
+            tracing::warn!("CPU pool saturated, rejecting job");
+            AppError::Overloaded { retry_after: RETRY_AFTER_SECS }
+        })?;
+
+        let (tx, rx) = oneshot::channel();
+        self.pool.spawn(move || {
+            let _permit = permit;
+            // A panic escaping a rayon spawn aborts the process, so it is
+            // caught here and turned into an error for this request only
+            let result = panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or_else(|payload| {
+                let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                tracing::error!("CPU pool job panicked: {}", message);
+                Err(AppError::Internal(format!("CPU pool job panicked: {}", message)))
+            });
+            let _ = tx.send(result);
+        });
+
+        rx.await
+            .map_err(|_| AppError::Internal("CPU pool job was dropped".to_string()))?
+    }
+}
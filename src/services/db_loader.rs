@@ -3,28 +3,222 @@ use tokio::sync::Mutex;
 use moka::sync::Cache;
 use polars::prelude::*;
 use crate::error::AppError;
-use tracing::{info, debug, warn};
+use tracing::{info, debug};
+use std::path::Path;
 use std::time::Duration;
 use std::sync::Arc;
+use serde::Serialize;
+use rusqlite::{ErrorCode, OptionalExtension};
+use rusqlite::types::ValueRef;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use crate::services::excel::utils::{dedupe_identifier, to_ascii_identifier};
+use crate::services::value_dictionary::ValueDictionary;
+use crate::services::sql_functions;
+use crate::services::schema::{render_schema, ColumnProfile, FullTextIndex, TableProfile, TableSource, UnionSource, SAMPLE_ROWS, TOP_VALUES};
 
 const BATCH_SIZE: usize = 1000;
 const CACHE_TTL: Duration = Duration::from_secs(3600); // 1 hour
 const CACHE_CAPACITY: u64 = 300;
+const PROFILE_CACHE_CAPACITY: u64 = 1000;
+/// The session database lives in memory or in a scratch file and is rebuilt
+/// from the workbook on failure, so durability is traded for load speed.
+const BULK_LOAD_PRAGMAS: &str = "
+    PRAGMA journal_mode = MEMORY;
+    PRAGMA synchronous = OFF;
+    PRAGMA temp_store = MEMORY;
+    PRAGMA cache_size = -65536;
+";
+/// Text columns with at most this many distinct values get a value dictionary.
+const MAX_DICTIONARY_VALUES: usize = 50;
+/// Text columns averaging at least this many characters get a full-text index.
+const FULL_TEXT_MIN_AVG_CHARS: f64 = 30.0;
+/// Smaller tables are scanned quickly enough that indexes don't pay off.
+const MIN_INDEXED_ROWS: usize = 1000;
+/// SQLite VM instructions between time budget checks.
+const PROGRESS_CHECK_INTERVAL: i32 = 10_000;
+/// Time allowed for inferring keys and relationships after a load.
+const DEFAULT_RELATIONSHIP_TIME_BUDGET: Duration = Duration::from_secs(2);
+/// A column is a candidate key when at least this share of its values is
+/// distinct and at least this share of rows is non-null.
+const KEY_UNIQUENESS: f64 = 0.95;
+/// Share of a column's distinct values that must exist in another table's
+/// primary key for the column to count as a foreign key.
+const FOREIGN_KEY_COVERAGE: f64 = 0.95;
+/// Columns whose name doesn't point at the referenced table need full
+/// coverage and at least this many distinct values, so small code sets
+/// (1, 2, 3...) don't match every numeric key.
+const UNNAMED_FOREIGN_KEY_MIN_DISTINCT: i64 = 20;
+/// Name fragments that mark a column as an identifier.
+const KEY_NAME_HINTS: [&str; 8] = ["id", "cod", "codigo", "chave", "key", "cpf", "cnpj", "sku"];
+/// Plural endings and their singular form, tried when matching column names
+/// against sheet names (`clientes` → `cliente`, `regioes` → `regiao`).
+const PLURAL_SUFFIXES: [(&str, &str); 4] = [("s", ""), ("es", ""), ("ies", "y"), ("oes", "ao")];
+/// Shorter sheet-name stems match too many columns to mean anything.
+const MIN_NAME_STEM_LEN: usize = 3;
+
+/// Links a loaded SQL column back to the header it came from in the workbook.
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnMapping {
+    pub sheet_name: String,
+    pub table_name: String,
+    pub original_header: String,
+    pub column_name: String,
+    pub inferred_type: String,
+}
+
+/// One row of the `_catalog` table: where a loaded table came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogEntry {
+    pub table_name: String,
+    pub file_name: String,
+    pub sheet_name: String,
+    pub cell_range: Option<String>,
+    pub row_count: usize,
+    pub loaded_at: String,
+    /// Inferred primary key: a unique, non-null identifier column.
+    pub primary_key: Option<String>,
+}
+
+/// A view stacking a workbook's sheets that share the same columns, with the
+/// sheet each row came from in `_source_sheet`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnionView {
+    pub view_name: String,
+    /// Member sheets in workbook order, and the table each was loaded into.
+    pub sheets: Vec<String>,
+    pub tables: Vec<String>,
+    pub row_count: usize,
+}
+
+/// An inferred foreign key: the values of `from_column` are (nearly all)
+/// values of `to_table.to_column`, its primary key.
+#[derive(Debug, Clone, Serialize)]
+pub struct Relationship {
+    pub from_table: String,
+    pub from_column: String,
+    pub to_table: String,
+    pub to_column: String,
+    /// Share of the distinct `from_column` values found in the target.
+    pub coverage: f64,
+}
+
+/// Limits on the indexes `create_indexes` builds for one table.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexBudget {
+    pub max_per_table: usize,
+    /// Time allowed for choosing columns and building indexes.
+    pub time_budget: Duration,
+}
+
+impl Default for IndexBudget {
+    fn default() -> Self {
+        Self { max_per_table: 4, time_budget: Duration::from_secs(2) }
+    }
+}
+
+/// An index built after loading a table, and why the column was chosen.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedIndex {
+    pub index_name: String,
+    pub column_name: String,
+    /// "key", "date" or "category".
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IndexReport {
+    pub indexes: Vec<CreatedIndex>,
+    pub elapsed_ms: f64,
+    /// Set when the time budget ran out before every candidate was indexed.
+    pub budget_exhausted: bool,
+}
 
 #[derive(Clone)]
 pub struct DbLoader {
     conn: Arc<Mutex<Connection>>,
     cache: Cache<String, DataFrame>,
+    /// Schema profiles by table or view. Any write can change a profile
+    /// (rows, keys, views, mappings), so every write empties the cache.
+    profiles: Cache<String, TableProfile>,
     current_table: Arc<Mutex<Option<String>>>,
     column_names: Arc<Mutex<Vec<String>>>,
+    full_text_search: bool,
+    index_budget: IndexBudget,
+    relationship_budget: Duration,
 }
 
 impl DbLoader {
     pub async fn new() -> Result<Self, AppError> {
+        Self::open(None).await
+    }
+
+    /// Opens a fresh database in the file at `path`, replacing any left
+    /// behind by an earlier run, or in memory when `path` is `None`.
+    pub async fn open(path: Option<&Path>) -> Result<Self, AppError> {
         info!("Creating new DbLoader instance");
-        let conn = Connection::open_in_memory()
-            .await
-            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+        let conn = match path {
+            Some(path) => {
+                if let Err(e) = std::fs::remove_file(path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        return Err(AppError::DatabaseError(format!("Failed to replace {}: {}", path.display(), e)));
+                    }
+                }
+                Connection::open(path).await
+            }
+            None => Connection::open_in_memory().await,
+        }
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        conn.call(|conn: &mut rusqlite::Connection| -> rusqlite::Result<()> {
+            conn.execute_batch(BULK_LOAD_PRAGMAS)?;
+            sql_functions::register(conn)?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS _column_map (
+                    table_name TEXT NOT NULL,
+                    sheet_name TEXT NOT NULL,
+                    position INTEGER NOT NULL,
+                    original_header TEXT NOT NULL,
+                    column_name TEXT NOT NULL,
+                    inferred_type TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS _catalog (
+                    table_name TEXT PRIMARY KEY,
+                    file_name TEXT NOT NULL,
+                    sheet_name TEXT NOT NULL,
+                    cell_range TEXT,
+                    row_count INTEGER NOT NULL,
+                    loaded_at TEXT NOT NULL,
+                    primary_key TEXT
+                );
+                CREATE TABLE IF NOT EXISTS _relationships (
+                    from_table TEXT NOT NULL,
+                    from_column TEXT NOT NULL,
+                    to_table TEXT NOT NULL,
+                    to_column TEXT NOT NULL,
+                    coverage REAL NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS _union_views (
+                    view_name TEXT NOT NULL,
+                    file_name TEXT NOT NULL,
+                    table_name TEXT NOT NULL,
+                    sheet_name TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS _column_values (
+                    table_name TEXT NOT NULL,
+                    column_name TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    frequency INTEGER NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS _full_text (
+                    table_name TEXT NOT NULL,
+                    fts_table TEXT NOT NULL,
+                    column_name TEXT NOT NULL
+                );"
+            )
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
 
         let cache = Cache::builder()
             .max_capacity(CACHE_CAPACITY)
@@ -35,12 +229,34 @@ impl DbLoader {
         Ok(Self {
             conn: Arc::new(Mutex::new(conn)),
             cache,
+            profiles: Cache::new(PROFILE_CACHE_CAPACITY),
             current_table: Arc::new(Mutex::new(None)),
             column_names: Arc::new(Mutex::new(Vec::new())),
+            full_text_search: true,
+            index_budget: IndexBudget::default(),
+            relationship_budget: DEFAULT_RELATIONSHIP_TIME_BUDGET,
         })
     }
 
+    pub fn with_index_budget(mut self, index_budget: IndexBudget) -> Self {
+        self.index_budget = index_budget;
+        self
+    }
+
+    /// Time allowed for `infer_relationships`.
+    pub fn with_relationship_budget(mut self, time_budget: Duration) -> Self {
+        self.relationship_budget = time_budget;
+        self
+    }
+
+    /// Whether `build_lookups` builds FTS5 indexes over long text columns.
+    pub fn with_full_text_search(mut self, enabled: bool) -> Self {
+        self.full_text_search = enabled;
+        self
+    }
+
     pub async fn load_dataframe(&self, df: DataFrame, table_name: &str) -> Result<(), AppError> {
+        self.profiles.invalidate_all();
         // Update metadata concurrently
         let metadata_update = async {
             *self.current_table.lock().await = Some(table_name.to_string());
@@ -59,55 +275,70 @@ impl DbLoader {
         tokio::join!(metadata_update, cache_update);
         
         let conn = self.conn.lock().await;
-        let df = df.clone();
         let table_name = table_name.to_string();
-        let this = self.clone();
-        
+        let create_table_sql = self.generate_create_table_sql(&table_name, &df.schema())?;
+
         conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<()> {
             let tx = conn.transaction()?;
             
             // Drop existing table if it exists
-            let drop_sql = format!("DROP TABLE IF EXISTS {}", table_name);
+            let drop_sql = format!("DROP TABLE IF EXISTS {}", quote_identifier(&table_name));
             tx.execute(&drop_sql, [])?;
 
             // Create table schema
-            let schema = df.schema();
-            let create_table_sql = this.generate_create_table_sql(&table_name, &schema)
-                .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
             tx.execute(&create_table_sql, [])?;
 
             // Generate simpler insert SQL with just one row of placeholders
-            let columns = df.get_column_names();
+            let columns: Vec<String> = df.get_column_names()
+                .into_iter()
+                .map(quote_identifier)
+                .collect();
             let placeholders = vec!["?"; df.width()].join(", ");
             let insert_sql = format!(
                 "INSERT INTO {} ({}) VALUES ({})",
-                table_name,
+                quote_identifier(&table_name),
                 columns.join(", "),
                 placeholders
             );
 
             {
                 let mut stmt = tx.prepare(&insert_sql)?;
-                
-                // Process in batches
+
+                // Columns that have no native SQLite type are loaded as text
+                let text_fallbacks: Vec<Option<Series>> = df.get_columns()
+                    .iter()
+                    .map(|series| if ColumnCursor::is_native(series.dtype()) {
+                        Ok(None)
+                    } else {
+                        series.cast(&DataType::String).map(Some)
+                    })
+                    .collect::<PolarsResult<_>>()
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                let mut cursors: Vec<ColumnCursor> = df.get_columns()
+                    .iter()
+                    .zip(&text_fallbacks)
+                    .map(|(series, fallback)| ColumnCursor::new(fallback.as_ref().unwrap_or(series)))
+                    .collect::<PolarsResult<_>>()
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+
+                // Bind each row straight from the column iterators into the
+                // statement's own parameter slots, so nothing is allocated per cell
                 let total_rows = df.height();
-                for chunk_start in (0..total_rows).step_by(BATCH_SIZE) {
-                    let chunk_end = (chunk_start + BATCH_SIZE).min(total_rows);
-                    debug!("Processing batch {}-{}/{}", chunk_start, chunk_end, total_rows);
-
-                    for row_idx in chunk_start..chunk_end {
-                        let params = this.prepare_row_params(&df, row_idx)
-                            .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
-                        let param_refs: Vec<&dyn rusqlite::ToSql> = params
-                            .iter()
-                            .map(|p| p as &dyn rusqlite::ToSql)
-                            .collect();
-
-                        stmt.execute(param_refs.as_slice())?;
+                for row_idx in 0..total_rows {
+                    if row_idx % BATCH_SIZE == 0 {
+                        debug!("Processing batch {}-{}/{}", row_idx, (row_idx + BATCH_SIZE).min(total_rows), total_rows);
+                    }
+                    for (col_idx, cursor) in cursors.iter_mut().enumerate() {
+                        cursor.bind_next(&mut stmt, col_idx + 1)?;
                     }
+                    stmt.raw_execute()?;
                 }
             }
-            
+
+            // Lookups built over the previous data are rebuilt by `build_lookups`
+            tx.execute("DELETE FROM _column_values WHERE table_name = ?1", [&table_name])?;
+            drop_full_text_index(&tx, &table_name)?;
+
             tx.commit()?;
             Ok(())
         })
@@ -115,90 +346,553 @@ impl DbLoader {
         .map_err(|e| AppError::DatabaseError(e.to_string()))
     }
 
-    fn prepare_row_params(&self, df: &DataFrame, row_idx: usize) -> Result<Vec<Box<dyn rusqlite::ToSql>>, AppError> {
-        let mut params = Vec::with_capacity(df.width());
-        for series in df.get_columns() {
-            let value = match series.get(row_idx) {
-                Ok(value) => match value {
-                    AnyValue::Null => Box::new(rusqlite::types::Null) as Box<dyn rusqlite::ToSql>,
-                    AnyValue::Int32(v) => Box::new(v) as Box<dyn rusqlite::ToSql>,
-                    AnyValue::Int64(v) => Box::new(v) as Box<dyn rusqlite::ToSql>,
-                    AnyValue::Float32(v) => Box::new(v as f64) as Box<dyn rusqlite::ToSql>,
-                    AnyValue::Float64(v) => Box::new(v) as Box<dyn rusqlite::ToSql>,
-                    AnyValue::String(v) => Box::new(v.to_string()) as Box<dyn rusqlite::ToSql>,
-                    AnyValue::Boolean(v) => Box::new(v) as Box<dyn rusqlite::ToSql>,
-                    _ => Box::new(value.to_string()) as Box<dyn rusqlite::ToSql>,
-                },
-                Err(e) => {
-                    warn!("Error getting value at row {}: {}", row_idx, e);
-                    Box::new(rusqlite::types::Null) as Box<dyn rusqlite::ToSql>
+    /// Builds a loaded table's value dictionary and, when enabled, its
+    /// full-text index. Both scan the whole table, so they run after the bulk
+    /// load instead of inside its transaction.
+    pub async fn build_lookups(&self, table_name: &str) -> Result<(), AppError> {
+        let conn = self.conn.lock().await;
+        let table_name = table_name.to_string();
+        let full_text_search = self.full_text_search;
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<()> {
+            let tx = conn.transaction()?;
+            build_value_dictionary(&tx, &table_name)?;
+            drop_full_text_index(&tx, &table_name)?;
+            if full_text_search {
+                build_full_text_index(&tx, &table_name)?;
+            }
+            tx.commit()
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    /// Picks the table name for a sheet. Reloading the same file and sheet reuses
+    /// its previous table; otherwise `base_name` gets a numeric suffix until it
+    /// no longer clashes with an existing table.
+    pub async fn reserve_table_name(&self, file_name: &str, sheet_name: &str, base_name: &str) -> Result<String, AppError> {
+        let conn = self.conn.lock().await;
+        let file_name = file_name.to_string();
+        let sheet_name = sheet_name.to_string();
+        let base_name = base_name.to_string();
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<String> {
+            let existing: Option<String> = conn.query_row(
+                "SELECT table_name FROM _catalog WHERE file_name = ?1 AND sheet_name = ?2",
+                [&file_name, &sheet_name],
+                |row| row.get(0),
+            ).optional()?;
+            if let Some(table_name) = existing {
+                return Ok(table_name);
+            }
+
+            let mut stmt = conn.prepare_cached(
+                "SELECT name FROM sqlite_master UNION SELECT table_name FROM _catalog"
+            )?;
+            let mut taken: HashSet<String> = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .filter_map(Result::ok)
+                .collect();
+            Ok(dedupe_identifier(base_name, &mut taken))
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn register_table(&self, entry: CatalogEntry) -> Result<(), AppError> {
+        self.profiles.invalidate_all();
+        let conn = self.conn.lock().await;
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<()> {
+            conn.execute(
+                "INSERT OR REPLACE INTO _catalog (table_name, file_name, sheet_name, cell_range, row_count, loaded_at, primary_key)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                rusqlite::params![
+                    entry.table_name,
+                    entry.file_name,
+                    entry.sheet_name,
+                    entry.cell_range,
+                    entry.row_count as i64,
+                    entry.loaded_at,
+                    entry.primary_key,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get_catalog(&self) -> Result<Vec<CatalogEntry>, AppError> {
+        let conn = self.conn.lock().await;
+
+        conn.call(|conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<CatalogEntry>> {
+            let mut stmt = conn.prepare_cached(
+                "SELECT table_name, file_name, sheet_name, cell_range, row_count, loaded_at, primary_key
+                 FROM _catalog ORDER BY loaded_at, table_name"
+            )?;
+            let entries = stmt
+                .query_map([], |row| Ok(CatalogEntry {
+                    table_name: row.get(0)?,
+                    file_name: row.get(1)?,
+                    sheet_name: row.get(2)?,
+                    cell_range: row.get(3)?,
+                    row_count: row.get::<_, i64>(4)? as usize,
+                    loaded_at: row.get(5)?,
+                    primary_key: row.get(6)?,
+                }))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(entries)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    /// Indexes the columns follow-up queries are most likely to filter, join
+    /// or group on: near-unique key columns first, then date columns, then
+    /// low-cardinality categories. Stops at the table's index budget.
+    pub async fn create_indexes(&self, table_name: &str) -> Result<IndexReport, AppError> {
+        let conn = self.conn.lock().await;
+        let table_name = table_name.to_string();
+        let budget = self.index_budget;
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<IndexReport> {
+            let start = std::time::Instant::now();
+            let deadline = start + budget.time_budget;
+            let mut report = IndexReport::default();
+
+            // Scans and index builds are interrupted once the budget runs out,
+            // not just checked between statements
+            conn.progress_handler(PROGRESS_CHECK_INTERVAL, Some(move || std::time::Instant::now() >= deadline));
+            let built = create_indexes_within(conn, &table_name, budget.max_per_table, &mut report);
+            conn.progress_handler(0, None::<fn() -> bool>);
+            match built {
+                Ok(()) => {}
+                Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) => report.budget_exhausted = true,
+                Err(e) => return Err(e),
+            }
+
+            report.elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+            if !report.indexes.is_empty() {
+                info!("Created {} indexes on {} in {:.1}ms", report.indexes.len(), table_name, report.elapsed_ms);
+            }
+            Ok(report)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    /// Creates a `UNION ALL` view over each group of `file_name`'s sheets
+    /// with identical column names and compatible types, replacing the
+    /// file's previous views. Views are named after `base_name`.
+    pub async fn create_union_views(&self, file_name: &str, base_name: &str) -> Result<Vec<UnionView>, AppError> {
+        self.profiles.invalidate_all();
+        let conn = self.conn.lock().await;
+        let file_name = file_name.to_string();
+        let base_name = base_name.to_string();
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<UnionView>> {
+            let tx = conn.transaction()?;
+            let mut old_stmt = tx.prepare("SELECT DISTINCT view_name FROM _union_views WHERE file_name = ?1")?;
+            let old_views: Vec<String> = old_stmt
+                .query_map([&file_name], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            drop(old_stmt);
+            for view_name in &old_views {
+                tx.execute_batch(&format!("DROP VIEW IF EXISTS {}", quote_identifier(view_name)))?;
+            }
+            tx.execute("DELETE FROM _union_views WHERE file_name = ?1", [&file_name])?;
+
+            let mut sheet_stmt = tx.prepare(
+                "SELECT table_name, sheet_name, row_count FROM _catalog WHERE file_name = ?1 ORDER BY loaded_at, table_name"
+            )?;
+            /// (table_name, sheet_name, row_count)
+            type Sheet = (String, String, i64);
+            /// (column name, SQL type) in table order
+            type Columns = Vec<(String, String)>;
+            let sheets: Vec<Sheet> = sheet_stmt
+                .query_map([&file_name], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            drop(sheet_stmt);
+
+            // Sheets grouped by column names, in order; a sheet whose types
+            // don't line up with the group's first sheet is left out
+            let mut groups: Vec<(Columns, Vec<Sheet>)> = Vec::new();
+            for (table_name, sheet_name, row_count) in sheets {
+                let mut info_stmt = tx.prepare(&format!("PRAGMA table_info({})", quote_identifier(&table_name)))?;
+                let columns: Columns = info_stmt
+                    .query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?
+                    .collect::<rusqlite::Result<_>>()?;
+                let group = groups.iter_mut().find(|(group_columns, _)| {
+                    group_columns.len() == columns.len()
+                        && group_columns.iter().zip(&columns).all(|(a, b)| a.0 == b.0)
+                });
+                match group {
+                    Some((group_columns, members)) => {
+                        if group_columns.iter().zip(&columns).all(|(a, b)| same_kind(&a.1, &b.1)) {
+                            members.push((table_name, sheet_name, row_count));
+                        } else {
+                            debug!("Sheet {} matches other sheets' columns but not their types", sheet_name);
+                        }
+                    }
+                    None => groups.push((columns, vec![(table_name, sheet_name, row_count)])),
                 }
-            };
-            params.push(value);
+            }
+
+            let mut taken_stmt = tx.prepare("SELECT name FROM sqlite_master UNION SELECT table_name FROM _catalog")?;
+            let mut taken: HashSet<String> = taken_stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            drop(taken_stmt);
+
+            let mut views = Vec::new();
+            for (columns, members) in groups.into_iter().filter(|(_, members)| members.len() > 1) {
+                let view_name = dedupe_identifier(base_name.clone(), &mut taken);
+                let column_list = columns.iter()
+                    .map(|(name, _)| quote_identifier(name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let selects = members.iter()
+                    .map(|(table_name, sheet_name, _)| format!(
+                        "SELECT '{}' AS _source_sheet, {} FROM {}",
+                        sheet_name.replace('\'', "''"),
+                        column_list,
+                        quote_identifier(table_name)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(" UNION ALL ");
+                tx.execute_batch(&format!("CREATE VIEW {} AS {}", quote_identifier(&view_name), selects))?;
+
+                for (table_name, sheet_name, _) in &members {
+                    tx.execute(
+                        "INSERT INTO _union_views (view_name, file_name, table_name, sheet_name) VALUES (?1, ?2, ?3, ?4)",
+                        [&view_name, &file_name, table_name, sheet_name],
+                    )?;
+                }
+                views.push(UnionView {
+                    view_name,
+                    row_count: members.iter().map(|(_, _, row_count)| *row_count as usize).sum(),
+                    sheets: members.iter().map(|(_, sheet_name, _)| sheet_name.clone()).collect(),
+                    tables: members.into_iter().map(|(table_name, _, _)| table_name).collect(),
+                });
+            }
+
+            tx.commit()?;
+            Ok(views)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    /// Drops every table loaded from `file_name`, along with its indexes,
+    /// metadata, relationships and the views over its sheets. Returns the
+    /// dropped tables.
+    pub async fn remove_file(&self, file_name: &str) -> Result<Vec<String>, AppError> {
+        self.profiles.invalidate_all();
+        let conn = self.conn.lock().await;
+        let file_name = file_name.to_string();
+
+        let (removed, remaining) = conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<(Vec<String>, i64)> {
+            let tx = conn.transaction()?;
+            let mut view_stmt = tx.prepare("SELECT DISTINCT view_name FROM _union_views WHERE file_name = ?1")?;
+            let views: Vec<String> = view_stmt
+                .query_map([&file_name], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            drop(view_stmt);
+            for view_name in &views {
+                tx.execute_batch(&format!("DROP VIEW IF EXISTS {}", quote_identifier(view_name)))?;
+            }
+            tx.execute("DELETE FROM _union_views WHERE file_name = ?1", [&file_name])?;
+
+            let mut table_stmt = tx.prepare("SELECT table_name FROM _catalog WHERE file_name = ?1")?;
+            let tables: Vec<String> = table_stmt
+                .query_map([&file_name], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            drop(table_stmt);
+            for table_name in &tables {
+                drop_full_text_index(&tx, table_name)?;
+                tx.execute_batch(&format!("DROP TABLE IF EXISTS {}", quote_identifier(table_name)))?;
+                tx.execute("DELETE FROM _column_map WHERE table_name = ?1", [table_name])?;
+                tx.execute("DELETE FROM _column_values WHERE table_name = ?1", [table_name])?;
+                tx.execute("DELETE FROM _relationships WHERE from_table = ?1 OR to_table = ?1", [table_name])?;
+                tx.execute("DELETE FROM _catalog WHERE table_name = ?1", [table_name])?;
+            }
+
+            let remaining = tx.query_row("SELECT COUNT(*) FROM _catalog", [], |row| row.get(0))?;
+            tx.commit()?;
+            Ok((tables, remaining))
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        for table_name in &removed {
+            self.cache.invalidate(table_name);
         }
-        Ok(params)
+        if remaining == 0 {
+            *self.current_table.lock().await = None;
+            self.column_names.lock().await.clear();
+        }
+        Ok(removed)
+    }
+
+    /// Empties the dataframe cache kept alongside the loaded tables.
+    pub fn clear_cache(&self) {
+        self.cache.invalidate_all();
+    }
+
+    pub async fn get_relationships(&self) -> Result<Vec<Relationship>, AppError> {
+        let conn = self.conn.lock().await;
+
+        conn.call(|conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<Relationship>> {
+            let mut stmt = conn.prepare_cached(
+                "SELECT from_table, from_column, to_table, to_column, coverage FROM _relationships ORDER BY rowid"
+            )?;
+            let relationships = stmt
+                .query_map([], |row| Ok(Relationship {
+                    from_table: row.get(0)?,
+                    from_column: row.get(1)?,
+                    to_table: row.get(2)?,
+                    to_column: row.get(3)?,
+                    coverage: row.get(4)?,
+                }))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(relationships)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    /// Infers a primary key for each of `new_tables`, then foreign keys
+    /// between them and every catalogued table (across files too) by
+    /// value-set inclusion. Relationships between older tables are kept, so
+    /// it can run again after each load. On large tables only key-like,
+    /// indexed or table-named columns are considered, and scanning stops at
+    /// the relationship time budget, keeping what was found by then.
+    pub async fn infer_relationships(&self, new_tables: &[String]) -> Result<Vec<Relationship>, AppError> {
+        self.profiles.invalidate_all();
+        let conn = self.conn.lock().await;
+        let new_tables: HashSet<String> = new_tables.iter().cloned().collect();
+        let time_budget = self.relationship_budget;
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<Relationship>> {
+            let tx = conn.transaction()?;
+            let mut table_stmt = tx.prepare(
+                "SELECT table_name, sheet_name, row_count, primary_key FROM _catalog ORDER BY loaded_at, table_name"
+            )?;
+            let tables: Vec<CatalogedTable> = table_stmt
+                .query_map([], |row| Ok(CatalogedTable {
+                    name: row.get(0)?,
+                    name_stems: name_stems(&row.get::<_, String>(1)?),
+                    row_count: row.get(2)?,
+                    primary_key: row.get(3)?,
+                    is_new: false,
+                }))?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .map(|table| CatalogedTable { is_new: new_tables.contains(&table.name), ..table })
+                .collect();
+            drop(table_stmt);
+
+            let deadline = std::time::Instant::now() + time_budget;
+            tx.progress_handler(PROGRESS_CHECK_INTERVAL, Some(move || std::time::Instant::now() >= deadline));
+            let mut inference = RelationshipInference::default();
+            let inferred = inference.run(&tx, &tables);
+            tx.progress_handler(0, None::<fn() -> bool>);
+            match inferred {
+                Ok(()) => {}
+                Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) => {
+                    tracing::warn!("Relationship inference ran out of time; keeping the keys found so far");
+                }
+                Err(e) => return Err(e),
+            }
+
+            for (table_name, primary_key) in &inference.primary_keys {
+                tx.execute("UPDATE _catalog SET primary_key = ?1 WHERE table_name = ?2", rusqlite::params![primary_key, table_name])?;
+            }
+            for table_name in &new_tables {
+                tx.execute("DELETE FROM _relationships WHERE from_table = ?1 OR to_table = ?1", [table_name])?;
+            }
+            {
+                let mut insert = tx.prepare(
+                    "INSERT INTO _relationships (from_table, from_column, to_table, to_column, coverage) VALUES (?1, ?2, ?3, ?4, ?5)"
+                )?;
+                for r in &inference.relationships {
+                    insert.execute(rusqlite::params![r.from_table, r.from_column, r.to_table, r.to_column, r.coverage])?;
+                }
+            }
+            tx.commit()?;
+            Ok(inference.relationships)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
     }
 
-    pub async fn get_schema_with_samples(&self) -> Result<String, AppError> {
+    /// Tables generated SQL may read: every catalogued table, the views over
+    /// them, their full-text indexes and the catalog itself.
+    pub async fn get_queryable_tables(&self) -> Result<Vec<String>, AppError> {
+        let conn = self.conn.lock().await;
+
+        conn.call(|conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<String>> {
+            let mut stmt = conn.prepare_cached(
+                "SELECT table_name FROM _catalog
+                 UNION SELECT view_name FROM _union_views
+                 UNION SELECT fts_table FROM _full_text
+                 UNION SELECT '_catalog'"
+            )?;
+            let tables = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(tables)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn save_column_mappings(&self, mappings: Vec<ColumnMapping>) -> Result<(), AppError> {
+        self.profiles.invalidate_all();
+        let conn = self.conn.lock().await;
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<()> {
+            let tx = conn.transaction()?;
+            {
+                let mut delete = tx.prepare("DELETE FROM _column_map WHERE table_name = ?1")?;
+                for table_name in mappings.iter().map(|m| &m.table_name).collect::<HashSet<_>>() {
+                    delete.execute([table_name])?;
+                }
+
+                let mut insert = tx.prepare(
+                    "INSERT INTO _column_map (table_name, sheet_name, position, original_header, column_name, inferred_type)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+                )?;
+                for (position, mapping) in mappings.iter().enumerate() {
+                    insert.execute(rusqlite::params![
+                        mapping.table_name,
+                        mapping.sheet_name,
+                        position as i64,
+                        mapping.original_header,
+                        mapping.column_name,
+                        mapping.inferred_type,
+                    ])?;
+                }
+            }
+            tx.commit()
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    /// The table's columns with the headers they were loaded from, in
+    /// column order. Empty for a table with no recorded mapping.
+    pub async fn column_mappings(&self, table_name: &str) -> Result<Vec<ColumnMapping>, AppError> {
+        let conn = self.conn.lock().await;
+        let table_name = table_name.to_string();
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<ColumnMapping>> {
+            let mut stmt = conn.prepare_cached(
+                "SELECT sheet_name, original_header, column_name, inferred_type
+                 FROM _column_map WHERE table_name = ?1 ORDER BY position"
+            )?;
+            let mappings = stmt
+                .query_map([&table_name], |row| Ok(ColumnMapping {
+                    sheet_name: row.get(0)?,
+                    table_name: table_name.clone(),
+                    original_header: row.get(1)?,
+                    column_name: row.get(2)?,
+                    inferred_type: row.get(3)?,
+                }))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(mappings)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get_value_dictionary(&self) -> Result<ValueDictionary, AppError> {
+        let conn = self.conn.lock().await;
+
+        conn.call(|conn: &mut rusqlite::Connection| -> rusqlite::Result<ValueDictionary> {
+            // Views over several sheets take the values of their sheets
+            let mut stmt = conn.prepare_cached(
+                "SELECT table_name, column_name, value, frequency FROM _column_values
+                 UNION ALL
+                 SELECT u.view_name, v.column_name, v.value, v.frequency
+                 FROM _column_values v JOIN _union_views u ON u.table_name = v.table_name
+                 ORDER BY frequency DESC"
+            )?;
+            let mut dictionary = ValueDictionary::default();
+            let rows = stmt.query_map([], |row| Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            )))?;
+            for row in rows {
+                let (table, column, value) = row?;
+                dictionary.insert(&table, &column, value);
+            }
+            Ok(dictionary)
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get_schema_with_samples(&self, token_budget: usize) -> Result<String, AppError> {
         if !self.has_data().await {
             return Ok("No data has been loaded into the database yet".to_string());
         }
         
         let conn = self.conn.lock().await;
         
-        conn.call(|conn: &mut rusqlite::Connection| -> rusqlite::Result<String> {
-            let mut schema = String::with_capacity(4096);
-            let mut table_stmt = conn.prepare_cached("SELECT name FROM sqlite_master WHERE type='table'")?;
+        let cached = self.profiles.clone();
+        let profiles = conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<TableProfile>> {
+            // Views over several sheets come first so they are the obvious
+            // choice. Tables prefixed with an underscore hold our own metadata;
+            // full-text indexes are virtual/shadow tables and are described
+            // with their table
+            let mut table_stmt = conn.prepare_cached(
+                "SELECT name FROM (
+                     SELECT view_name AS name, 0 AS kind, MIN(rowid) AS position FROM _union_views GROUP BY view_name
+                     UNION ALL
+                     SELECT name, 1, rowid FROM sqlite_master
+                     WHERE type='table' AND name NOT LIKE '\\_%' ESCAPE '\\'
+                       AND name NOT IN (SELECT name FROM pragma_table_list WHERE type IN ('virtual', 'shadow'))
+                 )
+                 ORDER BY kind, position"
+            )?;
             
             let table_names: Vec<String> = table_stmt
                 .query_map([], |row| row.get::<_, String>(0))?
                 .filter_map(Result::ok)
                 .collect();
 
-            for table_name in table_names {
-                schema.push_str(&format!("Table: {}\n", table_name));
-                
-                // Get column info
-                let cols_stmt = conn.prepare_cached(&format!(
-                    "SELECT * FROM {} LIMIT 1", table_name
-                ))?;
-                
-                let column_names: Vec<String> = cols_stmt
-                    .column_names()
-                    .into_iter()
-                    .map(String::from)
-                    .collect();
-
-                schema.push_str("Columns:\n");
-                for col in column_names {
-                    schema.push_str(&format!("  - {}\n", col));
-                }
-                schema.push_str("\n");
-            }
-
-            Ok(schema)
+            // Profiling scans every column, so it runs once per table until
+            // the next write rather than on every question
+            table_names.iter()
+                .map(|table_name| match cached.get(table_name) {
+                    Some(profile) => Ok(profile),
+                    None => {
+                        let profile = profile_table(conn, table_name)?;
+                        cached.insert(table_name.clone(), profile.clone());
+                        Ok(profile)
+                    }
+                })
+                .collect()
         })
         .await
-        .map_err(|e| AppError::DatabaseError(e.to_string()))
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        Ok(render_schema(&profiles, token_budget))
     }
 
     // Helper methods for SQL generation
     fn generate_create_table_sql(&self, table_name: &str, schema: &Schema) -> Result<String, AppError> {
         let columns: Vec<String> = schema
             .iter()
-            .map(|(name, dtype)| {
-                let sql_type = match dtype {
-                    DataType::Int32 | DataType::Int64 => "INTEGER",
-                    DataType::Float32 | DataType::Float64 => "REAL",
-                    _ => "TEXT",
-                };
-                format!("{} {}", name, sql_type)
-            })
+            .map(|(name, dtype)| format!("{} {}", quote_identifier(name), sql_type(dtype)))
             .collect();
     
         Ok(format!(
             "CREATE TABLE IF NOT EXISTS {} ({})",
-            table_name,
+            quote_identifier(table_name),
             columns.join(", ")
         ))
     }
@@ -223,9 +917,756 @@ impl DbLoader {
     }
 
     pub async fn get_connection(&self) -> Result<tokio::sync::MutexGuard<'_, Connection>, AppError> {
-        match self.conn.lock().await {
-            guard => Ok(guard)
+        Ok(self.conn.lock().await)
+    }
+}
+
+
+pub fn sql_type(dtype: &DataType) -> &'static str {
+    match dtype {
+        DataType::Int32 | DataType::Int64 => "INTEGER",
+        DataType::Float32 | DataType::Float64 => "REAL",
+        _ => "TEXT",
+    }
+}
+
+/// Quotes an identifier for use in SQL, escaping embedded double quotes.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Stores every distinct value of the table's low-cardinality text columns in
+/// `_column_values`, so generated WHERE clauses can be checked against them.
+fn build_value_dictionary(conn: &rusqlite::Connection, table_name: &str) -> rusqlite::Result<()> {
+    let quoted_table = quote_identifier(table_name);
+    conn.execute("DELETE FROM _column_values WHERE table_name = ?1", [table_name])?;
+
+    let mut info_stmt = conn.prepare(&format!("PRAGMA table_info({})", quoted_table))?;
+    let text_columns: Vec<String> = info_stmt
+        .query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .filter_map(Result::ok)
+        .filter(|(_, sql_type)| sql_type == "TEXT")
+        .map(|(name, _)| name)
+        .collect();
+
+    for column in text_columns {
+        let quoted = quote_identifier(&column);
+        // The LIMIT lets high-cardinality columns stop scanning early
+        let distinct: i64 = conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM (SELECT DISTINCT {q} FROM {t} WHERE {q} IS NOT NULL LIMIT {limit})",
+                q = quoted,
+                t = quoted_table,
+                limit = MAX_DICTIONARY_VALUES + 1
+            ),
+            [],
+            |row| row.get(0),
+        )?;
+        if distinct == 0 || distinct as usize > MAX_DICTIONARY_VALUES {
+            continue;
+        }
+        // Skip free-text/id-like columns where every value is unique
+        let non_null: i64 = conn.query_row(
+            &format!("SELECT COUNT({q}) FROM {t}", q = quoted, t = quoted_table),
+            [],
+            |row| row.get(0),
+        )?;
+        if distinct == non_null {
+            continue;
         }
+
+        conn.execute(
+            &format!(
+                "INSERT INTO _column_values (table_name, column_name, value, frequency)
+                 SELECT ?1, ?2, {q}, COUNT(*) FROM {t} WHERE {q} IS NOT NULL GROUP BY {q}",
+                q = quoted,
+                t = quoted_table
+            ),
+            [table_name, column.as_str()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Columns that could hold identifiers, with their SQL type. REAL columns
+/// qualify only when every value is a whole number, since Excel stores
+/// numeric IDs as floats. On tables large enough to be indexed, only
+/// key-like, indexed or table-named columns are scanned.
+fn key_columns(
+    conn: &rusqlite::Connection,
+    table: &CatalogedTable,
+    tables: &[CatalogedTable],
+) -> rusqlite::Result<Vec<(String, String)>> {
+    let table_name = table.name.as_str();
+    let quoted_table = quote_identifier(table_name);
+    let mut info_stmt = conn.prepare(&format!("PRAGMA table_info({})", quoted_table))?;
+    let columns: Vec<(String, String)> = info_stmt
+        .query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut indexed_stmt = conn.prepare_cached(
+        "SELECT DISTINCT i.name FROM sqlite_master m, pragma_index_info(m.name) i
+         WHERE m.type = 'index' AND m.tbl_name = ?1"
+    )?;
+    let indexed: HashSet<String> = indexed_stmt
+        .query_map([table_name], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let small = (table.row_count as usize) < MIN_INDEXED_ROWS;
+    let mut keys = Vec::new();
+    for (column, sql_type) in columns {
+        let candidate = small
+            || looks_like_key(&column)
+            || indexed.contains(&column)
+            || tables.iter().any(|table| names_table(&column, &table.name_stems));
+        if !candidate {
+            continue;
+        }
+        if sql_type == "REAL" {
+            let fractional: bool = conn.query_row(
+                &format!("SELECT EXISTS (SELECT 1 FROM {t} WHERE {q} <> ROUND({q}))", q = quote_identifier(&column), t = quoted_table),
+                [],
+                |row| row.get(0),
+            )?;
+            if fractional {
+                continue;
+            }
+        }
+        keys.push((column, sql_type));
+    }
+    Ok(keys)
+}
+
+/// INTEGER and whole-number REAL columns compare equal in SQLite; TEXT only
+/// matches TEXT.
+fn same_kind(a: &str, b: &str) -> bool {
+    (a == "TEXT") == (b == "TEXT")
+}
+
+fn looks_like_key(column: &str) -> bool {
+    column.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|part| KEY_NAME_HINTS.contains(&part))
+}
+
+/// The sheet part of a table's name in its plural and singular forms, e.g.
+/// `clientes`, `cliente` and `client` for sheet "Clientes". Table names also
+/// carry the file name (`vendas_2024_clientes`), which columns never repeat.
+fn name_stems(sheet_name: &str) -> Vec<String> {
+    let name = to_ascii_identifier(sheet_name);
+    let mut stems = vec![name.clone()];
+    for (plural, singular) in PLURAL_SUFFIXES {
+        if let Some(stem) = name.strip_suffix(plural) {
+            stems.push(format!("{}{}", stem, singular));
+        }
+    }
+    stems.retain(|stem| stem.len() >= MIN_NAME_STEM_LEN);
+    stems.dedup();
+    stems
+}
+
+/// Whether the column is named after the table, e.g. `cliente` or
+/// `id_cliente` for sheet "Clientes".
+fn names_table(column: &str, name_stems: &[String]) -> bool {
+    let column = column.to_lowercase();
+    name_stems.iter().any(|stem| column.contains(stem.as_str()))
+}
+
+/// A catalogued table as `infer_relationships` sees it.
+struct CatalogedTable {
+    name: String,
+    /// Forms of the sheet name a column referring to this table may use.
+    name_stems: Vec<String>,
+    row_count: i64,
+    primary_key: Option<String>,
+    /// Loaded since relationships were last inferred.
+    is_new: bool,
+}
+
+/// Keys and relationships found so far, kept when the time budget
+/// interrupts the scans.
+#[derive(Default)]
+struct RelationshipInference {
+    /// Primary keys inferred for new tables.
+    primary_keys: HashMap<String, Option<String>>,
+    relationships: Vec<Relationship>,
+}
+
+impl RelationshipInference {
+    fn run(&mut self, conn: &rusqlite::Connection, tables: &[CatalogedTable]) -> rusqlite::Result<()> {
+        let mut columns = HashMap::new();
+        for table in tables {
+            let key_columns = key_columns(conn, table, tables)?;
+            columns.insert(table.name.as_str(), key_columns);
+        }
+
+        let mut primary_keys: HashMap<&str, String> = HashMap::new();
+        for table in tables {
+            let primary_key = if table.is_new {
+                let primary_key = infer_primary_key(conn, &table.name, table.row_count, &columns[table.name.as_str()])?;
+                self.primary_keys.insert(table.name.clone(), primary_key.clone());
+                primary_key
+            } else {
+                table.primary_key.clone()
+            };
+            if let Some(primary_key) = primary_key {
+                primary_keys.insert(table.name.as_str(), primary_key);
+            }
+        }
+
+        // Links between two older tables were found by an earlier run
+        for from in tables {
+            for (from_column, from_type) in &columns[from.name.as_str()] {
+                if primary_keys.get(from.name.as_str()) == Some(from_column) {
+                    continue;
+                }
+                let mut best: Option<Relationship> = None;
+                for to in tables.iter().filter(|to| to.name != from.name && (from.is_new || to.is_new)) {
+                    let Some(to_column) = primary_keys.get(to.name.as_str()) else { continue };
+                    let compatible = columns[to.name.as_str()].iter()
+                        .any(|(name, ty)| name == to_column && same_kind(ty, from_type));
+                    if !compatible {
+                        continue;
+                    }
+                    let Some(coverage) = foreign_key(conn, &from.name, from_column, to, to_column)? else { continue };
+                    if best.as_ref().map_or(true, |best| coverage > best.coverage) {
+                        best = Some(Relationship {
+                            from_table: from.name.clone(),
+                            from_column: from_column.clone(),
+                            to_table: to.name.clone(),
+                            to_column: to_column.clone(),
+                            coverage,
+                        });
+                    }
+                }
+                self.relationships.extend(best);
+            }
+        }
+        Ok(())
     }
 }
 
+/// The first unique, non-null column, preferring identifier-like names.
+fn infer_primary_key(
+    conn: &rusqlite::Connection,
+    table_name: &str,
+    row_count: i64,
+    key_columns: &[(String, String)],
+) -> rusqlite::Result<Option<String>> {
+    if row_count < 2 {
+        return Ok(None);
+    }
+    let mut ordered: Vec<&String> = key_columns.iter().map(|(column, _)| column).collect();
+    ordered.sort_by_key(|column| !looks_like_key(column));
+
+    for column in ordered {
+        let (distinct, non_null): (i64, i64) = conn.query_row(
+            &format!(
+                "SELECT COUNT(DISTINCT {q}), COUNT({q}) FROM {t}",
+                q = quote_identifier(column),
+                t = quote_identifier(table_name)
+            ),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if non_null == row_count && distinct == row_count {
+            return Ok(Some(column.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// Coverage of `from_column`'s distinct values by `to_column` of table `to`, if
+/// high enough to call it a foreign key.
+fn foreign_key(
+    conn: &rusqlite::Connection,
+    from_table: &str,
+    from_column: &str,
+    to: &CatalogedTable,
+    to_column: &str,
+) -> rusqlite::Result<Option<f64>> {
+    // Unnamed links must look like keys, so other columns aren't scanned
+    let from_name = from_column.to_lowercase();
+    let named = from_name == to_column.to_lowercase()
+        || from_name.contains(&to_column.to_lowercase())
+        || names_table(from_column, &to.name_stems);
+    if !named && !looks_like_key(from_column) {
+        return Ok(None);
+    }
+
+    let (distinct, matched): (i64, i64) = conn.query_row(
+        &format!(
+            "SELECT COUNT(DISTINCT {f}), COUNT(DISTINCT CASE WHEN {f} IN (SELECT {k} FROM {to}) THEN {f} END)
+             FROM {from} WHERE {f} IS NOT NULL",
+            f = quote_identifier(from_column),
+            from = quote_identifier(from_table),
+            k = quote_identifier(to_column),
+            to = quote_identifier(&to.name),
+        ),
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if distinct < 2 {
+        return Ok(None);
+    }
+
+    let coverage = matched as f64 / distinct as f64;
+    let accepted = if named {
+        coverage >= FOREIGN_KEY_COVERAGE
+    } else {
+        coverage == 1.0 && distinct >= UNNAMED_FOREIGN_KEY_MIN_DISTINCT && looks_like_key(from_column)
+    };
+    Ok(accepted.then_some(coverage))
+}
+
+/// Builds the indexes `index_candidates` picks, at most `max_per_table`.
+/// Runs under `create_indexes`' deadline, which interrupts the statement in
+/// progress. An index name already used by another table gets a numeric
+/// suffix.
+fn create_indexes_within(
+    conn: &rusqlite::Connection,
+    table_name: &str,
+    max_per_table: usize,
+    report: &mut IndexReport,
+) -> rusqlite::Result<()> {
+    let (candidates, complete) = index_candidates(conn, table_name)?;
+    report.budget_exhausted = !complete;
+
+    let mut names_stmt = conn.prepare("SELECT lower(name), lower(tbl_name) FROM sqlite_master")?;
+    let existing: HashMap<String, String> = names_stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut taken: HashSet<String> = existing.keys().cloned().collect();
+
+    for (column_name, reason) in candidates.into_iter().take(max_per_table) {
+        let base_name = format!("{}_{}_idx", table_name, column_name).to_lowercase();
+        let index_name = match existing.get(&base_name) {
+            Some(indexed_table) if *indexed_table == table_name.to_lowercase() => base_name,
+            _ => dedupe_identifier(base_name, &mut taken),
+        };
+        conn.execute(&format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+            quote_identifier(&index_name),
+            quote_identifier(table_name),
+            quote_identifier(&column_name)
+        ), [])?;
+        report.indexes.push(CreatedIndex { index_name, column_name, reason });
+    }
+    Ok(())
+}
+
+/// Columns worth indexing, in priority order and tagged with why, and
+/// whether every column was examined before `create_indexes`' deadline
+/// interrupted the scan.
+fn index_candidates(
+    conn: &rusqlite::Connection,
+    table_name: &str,
+) -> rusqlite::Result<(Vec<(String, &'static str)>, bool)> {
+    let quoted_table = quote_identifier(table_name);
+    let row_count: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {}", quoted_table), [], |row| row.get(0))?;
+    if (row_count as usize) < MIN_INDEXED_ROWS {
+        return Ok((Vec::new(), true));
+    }
+
+    let mut info_stmt = conn.prepare(&format!("PRAGMA table_info({})", quoted_table))?;
+    let columns: Vec<(String, String)> = info_stmt
+        .query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut type_stmt = conn.prepare_cached(
+        "SELECT column_name, inferred_type FROM _column_map WHERE table_name = ?1"
+    )?;
+    let inferred: HashMap<String, String> = type_stmt
+        .query_map([table_name], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut category_stmt = conn.prepare_cached(
+        "SELECT column_name FROM _column_values WHERE table_name = ?1 GROUP BY column_name HAVING COUNT(*) > 1"
+    )?;
+    let categories: Vec<String> = category_stmt
+        .query_map([table_name], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let is_date = |column: &str| inferred.get(column).map_or(false, |t| t == "date" || t == "datetime");
+    let mut keys = Vec::new();
+    let mut complete = true;
+    for (column, sql_type) in &columns {
+        if sql_type == "REAL" || is_date(column) || categories.contains(column) {
+            continue;
+        }
+        let counts = conn.query_row(
+            &format!("SELECT COUNT(DISTINCT {q}), COUNT({q}) FROM {t}", q = quote_identifier(column), t = quoted_table),
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        );
+        // Out of time: index what was found so far
+        let (distinct, non_null) = match counts {
+            Ok(counts) => counts,
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) => {
+                complete = false;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        if non_null as f64 >= row_count as f64 * KEY_UNIQUENESS && distinct as f64 >= non_null as f64 * KEY_UNIQUENESS {
+            keys.push(column.clone());
+        }
+    }
+
+    let dates = columns.iter().map(|(column, _)| column).filter(|column| is_date(column));
+    let candidates = keys.into_iter().map(|column| (column, "key"))
+        .chain(dates.map(|column| (column.clone(), "date")))
+        .chain(categories.iter().filter(|column| !is_date(column)).map(|column| (column.clone(), "category")))
+        .collect();
+    Ok((candidates, complete))
+}
+
+/// Removes the table's FTS5 index, if it has one.
+fn drop_full_text_index(conn: &rusqlite::Connection, table_name: &str) -> rusqlite::Result<()> {
+    let fts_table: Option<String> = conn.query_row(
+        "SELECT fts_table FROM _full_text WHERE table_name = ?1 LIMIT 1",
+        [table_name],
+        |row| row.get(0),
+    ).optional()?;
+    if let Some(fts_table) = fts_table {
+        conn.execute(&format!("DROP TABLE IF EXISTS {}", quote_identifier(&fts_table)), [])?;
+        conn.execute("DELETE FROM _full_text WHERE table_name = ?1", [table_name])?;
+    }
+    Ok(())
+}
+
+/// Builds an FTS5 index over the table's long text columns, so word
+/// searches can use MATCH instead of `LIKE '%...%'` scans. The index reads
+/// its text from the table itself and ignores case and accents.
+fn build_full_text_index(conn: &rusqlite::Connection, table_name: &str) -> rusqlite::Result<()> {
+    let quoted_table = quote_identifier(table_name);
+    let mut info_stmt = conn.prepare(&format!("PRAGMA table_info({})", quoted_table))?;
+    let text_columns: Vec<String> = info_stmt
+        .query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .filter_map(Result::ok)
+        .filter(|(_, sql_type)| sql_type == "TEXT")
+        .map(|(name, _)| name)
+        .collect();
+
+    let mut long_columns = Vec::new();
+    for column in text_columns {
+        let avg_chars: Option<f64> = conn.query_row(
+            &format!("SELECT AVG(LENGTH({q})) FROM {t} WHERE {q} <> ''", q = quote_identifier(&column), t = quoted_table),
+            [],
+            |row| row.get(0),
+        )?;
+        if avg_chars.map_or(false, |avg| avg >= FULL_TEXT_MIN_AVG_CHARS) {
+            long_columns.push(column);
+        }
+    }
+    if long_columns.is_empty() {
+        return Ok(());
+    }
+
+    let fts_table = format!("{}_fts", table_name);
+    let taken: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+        [&fts_table],
+        |row| row.get(0),
+    )?;
+    if taken {
+        debug!("Skipping full-text index for {}: {} already exists", table_name, fts_table);
+        return Ok(());
+    }
+
+    let quoted_fts = quote_identifier(&fts_table);
+    let content_table = format!("'{}'", table_name.replace('\'', "''"));
+    let quoted_columns: Vec<String> = long_columns.iter().map(|c| quote_identifier(c)).collect();
+    conn.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE {fts} USING fts5({columns}, content={content}, content_rowid='rowid', tokenize='unicode61 remove_diacritics 2');
+         INSERT INTO {fts}({fts}) VALUES('rebuild');",
+        fts = quoted_fts,
+        columns = quoted_columns.join(", "),
+        content = content_table,
+    ))?;
+
+    let mut insert = conn.prepare("INSERT INTO _full_text (table_name, fts_table, column_name) VALUES (?1, ?2, ?3)")?;
+    for column in &long_columns {
+        insert.execute([table_name, fts_table.as_str(), column.as_str()])?;
+    }
+    info!("Built full-text index {} over {:?}", fts_table, long_columns);
+    Ok(())
+}
+
+/// Typed, sequential access to one column, used to bind rows without going
+/// through `AnyValue` for every cell.
+enum ColumnCursor<'a> {
+    Int32(Box<dyn PolarsIterator<Item = Option<i32>> + 'a>),
+    Int64(Box<dyn PolarsIterator<Item = Option<i64>> + 'a>),
+    Float32(Box<dyn PolarsIterator<Item = Option<f32>> + 'a>),
+    Float64(Box<dyn PolarsIterator<Item = Option<f64>> + 'a>),
+    Boolean(Box<dyn PolarsIterator<Item = Option<bool>> + 'a>),
+    Text(Box<dyn PolarsIterator<Item = Option<&'a str>> + 'a>),
+}
+
+impl<'a> ColumnCursor<'a> {
+    fn is_native(dtype: &DataType) -> bool {
+        matches!(
+            dtype,
+            DataType::Int32 | DataType::Int64 | DataType::Float32 | DataType::Float64 | DataType::Boolean | DataType::String
+        )
+    }
+
+    /// Cursor over a column whose dtype `is_native`; anything else must be cast to text first.
+    fn new(series: &'a Series) -> PolarsResult<Self> {
+        Ok(match series.dtype() {
+            DataType::Int32 => ColumnCursor::Int32(series.i32()?.into_iter()),
+            DataType::Int64 => ColumnCursor::Int64(series.i64()?.into_iter()),
+            DataType::Float32 => ColumnCursor::Float32(series.f32()?.into_iter()),
+            DataType::Float64 => ColumnCursor::Float64(series.f64()?.into_iter()),
+            DataType::Boolean => ColumnCursor::Boolean(series.bool()?.into_iter()),
+            _ => ColumnCursor::Text(series.str()?.into_iter()),
+        })
+    }
+
+    fn bind_next(&mut self, stmt: &mut rusqlite::Statement<'_>, index: usize) -> rusqlite::Result<()> {
+        match self {
+            ColumnCursor::Int32(iter) => stmt.raw_bind_parameter(index, iter.next().flatten()),
+            ColumnCursor::Int64(iter) => stmt.raw_bind_parameter(index, iter.next().flatten()),
+            ColumnCursor::Float32(iter) => stmt.raw_bind_parameter(index, iter.next().flatten().map(f64::from)),
+            ColumnCursor::Float64(iter) => stmt.raw_bind_parameter(index, iter.next().flatten()),
+            ColumnCursor::Boolean(iter) => stmt.raw_bind_parameter(index, iter.next().flatten()),
+            ColumnCursor::Text(iter) => stmt.raw_bind_parameter(index, iter.next().flatten()),
+        }
+    }
+}
+
+fn cell_to_string(value: ValueRef<'_>) -> String {
+    match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned(),
+        ValueRef::Blob(_) => "BLOB".to_string(),
+    }
+}
+
+/// Collects column types, null/distinct counts, frequent text values and the
+/// most complete rows of a table.
+fn profile_table(conn: &rusqlite::Connection, table_name: &str) -> rusqlite::Result<TableProfile> {
+    let quoted_table = quote_identifier(table_name);
+
+    let source = conn.query_row(
+        "SELECT file_name, sheet_name, cell_range FROM _catalog WHERE table_name = ?1",
+        [table_name],
+        |row| Ok(TableSource {
+            file_name: row.get(0)?,
+            sheet_name: row.get(1)?,
+            cell_range: row.get(2)?,
+        }),
+    ).optional()?;
+
+    let mut union_stmt = conn.prepare_cached(
+        "SELECT view_name, table_name, sheet_name FROM _union_views WHERE view_name = ?1 OR table_name = ?1 ORDER BY rowid"
+    )?;
+    let union_rows: Vec<(String, String, String)> = union_stmt
+        .query_map([table_name], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let part_of = union_rows.iter()
+        .find(|(_, member, _)| member == table_name)
+        .map(|(view_name, _, _)| view_name.clone());
+    let union_of = union_rows.iter()
+        .any(|(view_name, _, _)| view_name == table_name)
+        .then(|| UnionSource {
+            sheets: union_rows.iter().map(|(_, _, sheet_name)| sheet_name.clone()).collect(),
+            tables: union_rows.iter().map(|(_, member, _)| member.clone()).collect(),
+        });
+    // A view's columns are described by its first sheet's mappings
+    let mapped_table = union_of.as_ref()
+        .and_then(|union| union.tables.first())
+        .map_or(table_name, String::as_str);
+
+    let mut full_text_stmt = conn.prepare_cached(
+        "SELECT fts_table, column_name FROM _full_text WHERE table_name = ?1 ORDER BY rowid"
+    )?;
+    let full_text = full_text_stmt
+        .query_map([table_name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .fold(None, |index: Option<FullTextIndex>, (fts_table, column)| {
+            let mut index = index.unwrap_or(FullTextIndex { fts_table, columns: Vec::new() });
+            index.columns.push(column);
+            Some(index)
+        });
+
+    let mut mapping_stmt = conn.prepare_cached(
+        "SELECT column_name, original_header, inferred_type FROM _column_map WHERE table_name = ?1"
+    )?;
+    let mappings: HashMap<String, (String, String)> = mapping_stmt
+        .query_map([mapped_table], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let primary_key: Option<String> = conn.query_row(
+        "SELECT primary_key FROM _catalog WHERE table_name = ?1",
+        [table_name],
+        |row| row.get(0),
+    ).optional()?.flatten();
+    let mut references_stmt = conn.prepare_cached(
+        "SELECT from_column, to_table, to_column FROM _relationships WHERE from_table = ?1"
+    )?;
+    let references: HashMap<String, (String, String)> = references_stmt
+        .query_map([table_name], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut info_stmt = conn.prepare(&format!("PRAGMA table_info({})", quoted_table))?;
+    let mut columns: Vec<ColumnProfile> = info_stmt
+        .query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .map(|(name, sql_type)| {
+            let mapping = mappings.get(&name);
+            // Expression columns of a view carry no declared type
+            let sql_type = if sql_type.is_empty() && union_of.is_some() { "TEXT".to_string() } else { sql_type };
+            ColumnProfile {
+                primary_key: primary_key.as_ref() == Some(&name),
+                references: references.get(&name).cloned(),
+                original_header: mapping.map(|(original, _)| original.clone()),
+                inferred_type: mapping.map(|(_, inferred)| inferred.clone()),
+                name,
+                sql_type,
+                null_count: 0,
+                distinct_count: 0,
+                top_values: Vec::new(),
+                values: Vec::new(),
+            }
+        })
+        .collect();
+
+    let quoted_columns: Vec<String> = columns.iter().map(|c| quote_identifier(&c.name)).collect();
+    let mut stats_sql = String::from("SELECT COUNT(*)");
+    for c in &quoted_columns {
+        let _ = write!(stats_sql, ", COUNT({c}), COUNT(DISTINCT {c})");
+    }
+    let _ = write!(stats_sql, " FROM {}", quoted_table);
+    let row_count = conn.query_row(&stats_sql, [], |row| {
+        for (idx, column) in columns.iter_mut().enumerate() {
+            let non_null: i64 = row.get(1 + idx * 2)?;
+            column.distinct_count = row.get::<_, i64>(2 + idx * 2)? as usize;
+            column.null_count = row.get::<_, i64>(0)? as usize - non_null as usize;
+        }
+        row.get::<_, i64>(0)
+    })? as usize;
+
+    for (column, quoted) in columns.iter_mut().zip(&quoted_columns) {
+        // Unique columns (ids, free text) have no meaningful "top" values
+        if column.sql_type != "TEXT" || column.distinct_count == 0 || column.distinct_count == row_count - column.null_count {
+            continue;
+        }
+        let mut top_stmt = conn.prepare(&format!(
+            "SELECT {q}, COUNT(*) AS n FROM {t} WHERE {q} IS NOT NULL GROUP BY {q} ORDER BY n DESC LIMIT {limit}",
+            q = quoted,
+            t = quoted_table,
+            limit = TOP_VALUES
+        ))?;
+        column.top_values = top_stmt
+            .query_map([], |row| Ok((cell_to_string(row.get_ref(0)?), row.get::<_, i64>(1)? as usize)))?
+            .collect::<rusqlite::Result<_>>()?;
+    }
+
+    let mut values_stmt = conn.prepare_cached(
+        "SELECT value FROM _column_values
+         WHERE column_name = ?2
+           AND (table_name = ?1 OR table_name IN (SELECT table_name FROM _union_views WHERE view_name = ?1))
+         GROUP BY value ORDER BY SUM(frequency) DESC, value"
+    )?;
+    for column in columns.iter_mut() {
+        column.values = values_stmt
+            .query_map([table_name, column.name.as_str()], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+    }
+
+    // Prefer the most complete rows as samples
+    let completeness = quoted_columns.iter()
+        .map(|c| format!("({} IS NULL)", c))
+        .collect::<Vec<_>>()
+        .join(" + ");
+    // Views have no rowid to break ties with
+    let mut sample_stmt = conn.prepare(&format!(
+        "SELECT * FROM {} ORDER BY {}{} LIMIT {}",
+        quoted_table,
+        if completeness.is_empty() { "1".to_string() } else { completeness },
+        if union_of.is_some() { "" } else { ", rowid" },
+        SAMPLE_ROWS
+    ))?;
+    let width = columns.len();
+    let sample_rows = sample_stmt
+        .query_map([], |row| (0..width).map(|i| row.get_ref(i).map(cell_to_string)).collect())?
+        .collect::<rusqlite::Result<Vec<Vec<String>>>>()?;
+
+    Ok(TableProfile {
+        name: table_name.to_string(),
+        source,
+        row_count,
+        columns,
+        sample_rows,
+        full_text,
+        union_of,
+        part_of,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::excel::utils::sheet_table_name;
+
+    #[test]
+    fn names_table_matches_the_sheet_part_of_table_names() {
+        let clientes = name_stems("Clientes");
+        assert_eq!(sheet_table_name("Vendas 2024.xlsx", "Clientes"), "vendas_2024_clientes");
+        assert!(names_table("cliente_id", &clientes));
+        assert!(names_table("Codigo_Cliente", &clientes));
+        assert!(names_table("clientes", &clientes));
+        assert!(!names_table("valor_total", &clientes));
+
+        assert!(names_table("fornecedor_id", &name_stems("Fornecedores")));
+        assert!(names_table("regiao", &name_stems("Regiões")));
+        assert!(names_table("category_id", &name_stems("Categories")));
+        // "UF" is too short to tell a column named after it apart
+        assert!(name_stems("UF").is_empty());
+    }
+
+    async fn load_sheet(db_loader: &DbLoader, file_name: &str, sheet_name: &str, df: DataFrame) -> String {
+        let table_name = sheet_table_name(file_name, sheet_name);
+        let row_count = df.height();
+        db_loader.load_dataframe(df, &table_name).await.unwrap();
+        db_loader.register_table(CatalogEntry {
+            table_name: table_name.clone(),
+            file_name: file_name.to_string(),
+            sheet_name: sheet_name.to_string(),
+            cell_range: None,
+            row_count,
+            loaded_at: chrono::Utc::now().to_rfc3339(),
+            primary_key: None,
+        }).await.unwrap();
+        table_name
+    }
+
+    #[tokio::test]
+    async fn infers_foreign_keys_named_after_the_sheet() {
+        let db_loader = DbLoader::new().await.unwrap();
+        let codes: Vec<i64> = (1..=30).collect();
+        let names: Vec<String> = codes.iter().map(|code| format!("Cliente {}", code)).collect();
+        let clientes = load_sheet(&db_loader, "Vendas 2024.xlsx", "Clientes", df!(
+            "codigo" => &codes,
+            "nome" => &names,
+        ).unwrap()).await;
+
+        let pedido_ids: Vec<i64> = (1..=60).collect();
+        let cliente: Vec<i64> = pedido_ids.iter().map(|id| id % 25 + 1).collect();
+        let pedidos = load_sheet(&db_loader, "Vendas 2024.xlsx", "Pedidos", df!(
+            "pedido_id" => &pedido_ids,
+            "cliente" => &cliente,
+        ).unwrap()).await;
+
+        let relationships = db_loader.infer_relationships(&[clientes.clone(), pedidos.clone()]).await.unwrap();
+        let link = relationships.iter()
+            .find(|relationship| relationship.from_table == pedidos && relationship.from_column == "cliente")
+            .expect("pedidos.cliente should reference clientes");
+        assert_eq!(link.to_table, clientes);
+        assert_eq!(link.to_column, "codigo");
+        assert_eq!(link.coverage, 1.0);
+    }
+}
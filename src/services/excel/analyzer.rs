@@ -1,125 +1,59 @@
 use super::types::*;
 use super::utils::*;
-use std::io::Cursor;
-use bytes::Bytes;
-use calamine::{Data, Xlsx, open_workbook_from_rs};
+use super::workbook::ParsedWorkbook;
+use calamine::Data;
 use std::collections::HashSet;
 use smallvec::SmallVec;
 use crate::error::AppError;
 use rayon::prelude::*;
-use calamine::Reader;
-use std::sync::{Arc, Mutex};
 use super::types::SAMPLE_SIZE;
-const TYPE_DETECTION_ROWS: usize = 100;
+/// Data rows per column that null, distinct and min/max counts look at.
+const COLUMN_PROFILE_ROWS: usize = 100;
 pub struct ExcelAnalyzer;
 
 impl ExcelAnalyzer {
-    pub async fn analyze_from_bytes(&self, file_data: Bytes) -> Result<SheetAnalysis, AppError> {
+    pub fn analyze(&self, workbook: &ParsedWorkbook) -> Result<SheetAnalysis, AppError> {
         let start = std::time::Instant::now();
-        tracing::info!("Starting Excel file analysis from bytes");
-        
-        // Create a memory-mapped file for better performance with large files
-        let cursor = Cursor::new(file_data);
-        
-        tracing::info!("Opening workbook...");
-        let workbook_start = std::time::Instant::now();
-        let mut workbook: Xlsx<_> = open_workbook_from_rs(cursor)
-            .map_err(|e| {
-                tracing::error!("Failed to open Excel file: {}", e);
-                AppError::FileProcessingError(format!("Failed to open Excel file: {}", e))
-            })?;
-        tracing::info!("Workbook opened in {:?}", workbook_start.elapsed());
-        
-        let sheet_names: Vec<String> = workbook.sheet_names().to_vec();
+        tracing::info!("Starting Excel workbook analysis");
+
+        let sheet_names = workbook.sheet_names();
         tracing::info!("Found {} sheets: {:?}", sheet_names.len(), sheet_names);
         
-        if let Some(sheet_name) = sheet_names.first() {
-            let worksheets = workbook.worksheets();
-            if let Some((_, range)) = worksheets.into_iter().find(|(name, _)| name == sheet_name) {
-                // Use a streaming iterator for rows to reduce memory usage
-                let mut rows = Vec::with_capacity(1000);
-                let mut row_iter = range.rows();
-                
-                // Process header row separately
-                if let Some(header_row) = row_iter.next() {
-                    rows.push(header_row.to_vec());
-                    
-                    // Process remaining rows in chunks
-                    for row in row_iter.take(999) {
-                        rows.push(row.to_vec());
-                    }
-                }
+        if let Some(sheet) = workbook.sheets.first() {
+            if sheet.error.is_none() {
+                // Profile the header and the first rows only
+                let rows = &sheet.rows[..sheet.rows.len().min(1000)];
     
                 let row_count = rows.len();
                 let column_count = rows.first().map_or(0, |r| r.len());
-                
-                // Process headers with thread-safe name tracking
-                let mut existing_names = HashSet::new();
-                let headers = rows.first()
-                    .map(|row| {
-                        row.iter()
-                            .map(|cell| clean_column_name(&cell.to_string(), &mut existing_names))
-                            .collect::<Vec<_>>()
-                    })
-                    .unwrap_or_default();
-            
-                    let date_columns = Arc::new(Mutex::new(Vec::new()));
-                    let numeric_columns = Arc::new(Mutex::new(Vec::new()));
-                    let text_columns = Arc::new(Mutex::new(Vec::new()));
+                let headers = &sheet.columns;
             
-    
+                // Column types come from the parsed sheet, which looks at
+                // every data row, so the analysis agrees with the loaded table
                 let column_info: Vec<ColumnInfo> = headers.par_iter()
-                .enumerate()
-                .map(|(idx, name)| {
-                    let values: Vec<Data> = rows.iter()
-                        .skip(1)
-                        .take(TYPE_DETECTION_ROWS)
-                        .filter_map(|row| row.get(idx))
-                        .cloned()
-                        .collect();
-                    
-                    let data_type = self.detect_column_type(&values);
-                    
-                    // Use thread-safe operations for column categorization
-                    match data_type.as_str() {
-                        "date" => {
-                            if let Ok(mut cols) = date_columns.lock() {
-                                cols.push(name.clone());
-                            }
-                        },
-                        "numeric" => {
-                            if let Ok(mut cols) = numeric_columns.lock() {
-                                cols.push(name.clone());
-                            }
-                        },
-                        "string" => {
-                            if let Ok(mut cols) = text_columns.lock() {
-                                cols.push(name.clone());
-                            }
-                        },
-                        _ => {}
-                    }
-                    
-                    self.analyze_column(&values, name)
-                })
-                .collect();
-            
-            // Before creating SheetAnalysis, unwrap the mutex values
-            let date_columns = Arc::try_unwrap(date_columns)
-                .unwrap_or_else(|_| panic!("Failed to unwrap date_columns"))
-                .into_inner()
-                .unwrap_or_default();
-            
-            let numeric_columns = Arc::try_unwrap(numeric_columns)
-                .unwrap_or_else(|_| panic!("Failed to unwrap numeric_columns"))
-                .into_inner()
-                .unwrap_or_default();
-            
-            let text_columns = Arc::try_unwrap(text_columns)
-                .unwrap_or_else(|_| panic!("Failed to unwrap text_columns"))
-                .into_inner()
-                .unwrap_or_default();
-    
+                    .enumerate()
+                    .map(|(idx, name)| {
+                        let values: Vec<Data> = rows.iter()
+                            .skip(1)
+                            .take(COLUMN_PROFILE_ROWS)
+                            .filter_map(|row| row.get(idx))
+                            .cloned()
+                            .collect();
+                        self.analyze_column(&values, name, sheet.column_types[idx])
+                    })
+                    .collect();
+
+                let columns_of_type = |data_type: &str| -> Vec<String> {
+                    headers.iter()
+                        .zip(&sheet.column_types)
+                        .filter(|(_, &column_type)| column_type == data_type)
+                        .map(|(name, _)| name.clone())
+                        .collect()
+                };
+                let date_columns = columns_of_type("date");
+                let numeric_columns = columns_of_type("numeric");
+                let text_columns = columns_of_type("string");
+
                 tracing::info!("Analysis completed in {:?}", start.elapsed());
                 
 
@@ -144,13 +78,13 @@ impl ExcelAnalyzer {
                     text_columns,
                 })
             } else {
-                Err(AppError::FileProcessingError("Failed to read worksheet".to_string()))
+                Err(AppError::FileProcessingError(sheet.error.clone().unwrap_or_default()))
             }
         } else {
             Err(AppError::FileProcessingError("No sheets found in workbook".to_string()))
         }
     }
-    fn analyze_column(&self, values: &[Data], name: &str) -> ColumnInfo {
+    fn analyze_column(&self, values: &[Data], name: &str, data_type: &str) -> ColumnInfo {
         let mut sample_values = SmallVec::<[String; SAMPLE_SIZE]>::new();
     
     let (null_count, seen_values, min_max) = values.par_iter()
@@ -192,7 +126,7 @@ impl ExcelAnalyzer {
 
     ColumnInfo {
         name: name.to_string(),
-        data_type: self.detect_column_type(values),
+        data_type: data_type.to_string(),
         sample_values,
         null_count,
         unique_count: seen_values.len(),
@@ -201,40 +135,4 @@ impl ExcelAnalyzer {
         has_duplicates: seen_values.len() < values.len() - null_count,
     }
     }
-
-    fn detect_column_type(&self, values: &[Data]) -> String {
-        let (numeric_count, date_count, bool_count, empty_count) = values.par_iter()
-            .take(TYPE_DETECTION_ROWS)
-            .filter(|v| !matches!(v, Data::Empty))
-            .fold(
-                || (0, 0, 0, 0),
-                |(mut num, mut date, mut bool, mut empty), value| {
-                    match value {
-                        Data::Float(_) | Data::Int(_) => num += 1,
-                        Data::DateTime(_) => date += 1,
-                        Data::String(s) if is_date_string(s) => date += 1,
-                        Data::Bool(_) => bool += 1,
-                        Data::Empty => empty += 1,
-                        _ => {}
-                    }
-                    (num, date, bool, empty)
-                }
-            )
-            .reduce(|| (0, 0, 0, 0),
-                |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2, a.3 + b.3)
-            );
-
-    let total = values.len() - empty_count;
-    if total == 0 {
-        return "empty".to_string();
-    }
-
-    let threshold = total as f64 * 0.8;
-    match () {
-        _ if numeric_count as f64 >= threshold => "numeric",
-        _ if date_count as f64 >= threshold => "date",
-        _ if bool_count as f64 >= threshold => "boolean",
-        _ => "string",
-    }.to_string()
-    }
-}
\ No newline at end of file
+}
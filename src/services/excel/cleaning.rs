@@ -0,0 +1,191 @@
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::error::AppError;
+
+/// A single step of the cleaning pipeline. Steps run in the order given by the
+/// request, so e.g. `nullify_markers` should come before `drop_empty_rows` if
+/// rows made only of "N/A" cells are meant to be removed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum CleaningStep {
+    TrimWhitespace,
+    NullifyMarkers {
+        #[serde(default = "default_null_markers")]
+        markers: Vec<String>,
+    },
+    DropEmptyRows,
+    DropEmptyColumns,
+    DedupeRows,
+}
+
+impl CleaningStep {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CleaningStep::TrimWhitespace => "trim_whitespace",
+            CleaningStep::NullifyMarkers { .. } => "nullify_markers",
+            CleaningStep::DropEmptyRows => "drop_empty_rows",
+            CleaningStep::DropEmptyColumns => "drop_empty_columns",
+            CleaningStep::DedupeRows => "dedupe_rows",
+        }
+    }
+}
+
+fn default_null_markers() -> Vec<String> {
+    ["", "N/A", "n/a", "#N/A"].iter().map(|s| s.to_string()).collect()
+}
+
+/// What a single step did to the dataframe.
+#[derive(Debug, Clone, Serialize)]
+pub struct StepReport {
+    pub step: String,
+    pub rows_removed: usize,
+    pub columns_removed: Vec<String>,
+    pub cells_changed: usize,
+}
+
+impl StepReport {
+    fn new(step: &CleaningStep) -> Self {
+        Self {
+            step: step.name().to_string(),
+            rows_removed: 0,
+            columns_removed: Vec::new(),
+            cells_changed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CleaningPipeline {
+    steps: Vec<CleaningStep>,
+}
+
+impl Default for CleaningPipeline {
+    fn default() -> Self {
+        Self::new(vec![
+            CleaningStep::TrimWhitespace,
+            CleaningStep::NullifyMarkers { markers: default_null_markers() },
+            CleaningStep::DropEmptyRows,
+            CleaningStep::DropEmptyColumns,
+        ])
+    }
+}
+
+impl CleaningPipeline {
+    pub fn new(steps: Vec<CleaningStep>) -> Self {
+        Self { steps }
+    }
+
+    pub fn run(&self, mut df: DataFrame) -> Result<(DataFrame, Vec<StepReport>), AppError> {
+        let mut reports = Vec::with_capacity(self.steps.len());
+
+        for step in &self.steps {
+            let mut report = StepReport::new(step);
+            df = match step {
+                CleaningStep::TrimWhitespace => {
+                    map_string_columns(df, &mut report, |s| {
+                        let trimmed = s.trim();
+                        (trimmed.len() != s.len()).then_some(Some(trimmed))
+                    })?
+                }
+                CleaningStep::NullifyMarkers { markers } => {
+                    map_string_columns(df, &mut report, |s| {
+                        markers.iter().any(|m| m == s).then_some(None)
+                    })?
+                }
+                CleaningStep::DropEmptyRows => drop_empty_rows(df, &mut report)?,
+                CleaningStep::DropEmptyColumns => drop_empty_columns(df, &mut report)?,
+                CleaningStep::DedupeRows => {
+                    let before = df.height();
+                    let deduped = df
+                        .unique_stable(None, UniqueKeepStrategy::First, None)
+                        .map_err(|e| AppError::DataFrameError(format!("Failed to dedupe rows: {}", e)))?;
+                    report.rows_removed = before - deduped.height();
+                    deduped
+                }
+            };
+            tracing::debug!(
+                "Cleaning step {} removed {} rows, {} columns, changed {} cells",
+                report.step,
+                report.rows_removed,
+                report.columns_removed.len(),
+                report.cells_changed
+            );
+            reports.push(report);
+        }
+
+        Ok((df, reports))
+    }
+}
+
+/// Rewrites string cells where `f` returns `Some(new_value)`, counting them as changed.
+fn map_string_columns<F>(df: DataFrame, report: &mut StepReport, f: F) -> Result<DataFrame, AppError>
+where
+    F: Fn(&str) -> Option<Option<&str>>,
+{
+    let mut columns = Vec::with_capacity(df.width());
+    for series in df.get_columns() {
+        if series.dtype() != &DataType::String {
+            columns.push(series.clone());
+            continue;
+        }
+
+        let ca = series.str()
+            .map_err(|e| AppError::DataFrameError(e.to_string()))?;
+        let mapped: StringChunked = ca.into_iter()
+            .map(|value| match value {
+                Some(s) => match f(s) {
+                    Some(replacement) => {
+                        report.cells_changed += 1;
+                        replacement
+                    }
+                    None => Some(s),
+                },
+                None => None,
+            })
+            .collect();
+        columns.push(mapped.with_name(series.name()).into_series());
+    }
+
+    DataFrame::new(columns)
+        .map_err(|e| AppError::DataFrameError(format!("Failed to rebuild DataFrame: {}", e)))
+}
+
+/// A cell is empty when it is null or a string made only of whitespace.
+fn empty_mask(series: &Series) -> Result<BooleanChunked, AppError> {
+    if series.dtype() == &DataType::String {
+        let ca = series.str()
+            .map_err(|e| AppError::DataFrameError(e.to_string()))?;
+        Ok(ca.into_iter()
+            .map(|value| value.map_or(true, |s| s.trim().is_empty()))
+            .collect())
+    } else {
+        Ok(series.is_null())
+    }
+}
+
+fn drop_empty_rows(df: DataFrame, report: &mut StepReport) -> Result<DataFrame, AppError> {
+    let mut all_empty = BooleanChunked::full("all_empty", true, df.height());
+    for series in df.get_columns() {
+        all_empty = &all_empty & &empty_mask(series)?;
+    }
+
+    let before = df.height();
+    let filtered = df.filter(&!&all_empty)
+        .map_err(|e| AppError::DataFrameError(format!("Failed to drop empty rows: {}", e)))?;
+    report.rows_removed = before - filtered.height();
+    Ok(filtered)
+}
+
+fn drop_empty_columns(df: DataFrame, report: &mut StepReport) -> Result<DataFrame, AppError> {
+    let mut keep = Vec::with_capacity(df.width());
+    for series in df.get_columns() {
+        if series.is_empty() || empty_mask(series)?.all() {
+            report.columns_removed.push(series.name().to_string());
+        } else {
+            keep.push(series.clone());
+        }
+    }
+
+    DataFrame::new(keep)
+        .map_err(|e| AppError::DataFrameError(format!("Failed to drop empty columns: {}", e)))
+}
@@ -1,7 +1,11 @@
 pub mod analyzer;
+pub mod cleaning;
 pub mod processor;
 pub mod types;
 pub mod utils;
+pub mod workbook;
 
 pub use analyzer::ExcelAnalyzer;
+pub use cleaning::{CleaningPipeline, CleaningStep};
 pub use processor::ExcelProcessor;
+pub use workbook::ParsedWorkbook;
@@ -1,150 +1,293 @@
+use super::cleaning::{CleaningPipeline, StepReport};
+use super::types::{ProcessingReport, SheetReport, SheetStatus, TypeCoercion};
 use super::utils::*;
-use std::io::Cursor;
-use bytes::Bytes;
-use calamine::{Data, Xlsx, open_workbook_from_rs, Reader};
-use std::collections::HashSet;
+use super::workbook::{ParsedSheet, ParsedWorkbook};
+use std::collections::HashMap;
+use std::sync::Arc;
+use calamine::Data;
 use crate::error::AppError;
-use crate::services::db_loader::DbLoader;
+use crate::services::cpu_pool::CpuPool;
+use crate::services::db_loader::{CatalogEntry, ColumnMapping, DbLoader};
 use polars::prelude::DataFrame;
 use polars::prelude::*;
 use polars::series::Series;
 use polars::datatypes::{DataType, TimeUnit};
+use rayon::prelude::*;
 
+/// A sheet whose dataframe has been built, cleaned and normalized, waiting
+/// for its turn to be written to SQLite.
+struct PreparedSheet {
+    report: SheetReport,
+    cell_range: Option<String>,
+    headers: Vec<String>,
+    original_headers: Vec<String>,
+    df: DataFrame,
+}
+
+/// Result of preparing one sheet: either ready to load, or already final
+/// because it was empty or failed.
+enum DecodedSheet {
+    Ready(PreparedSheet),
+    Finished(SheetReport),
+}
+
+#[derive(Clone)]
 pub struct ExcelProcessor {
     db_loader: DbLoader,
+    cleaning: CleaningPipeline,
+    cpu_pool: CpuPool,
 }
 
 impl ExcelProcessor {
-    pub fn new(db_loader: DbLoader) -> Self {
-        Self { db_loader }
+    pub fn new(db_loader: DbLoader, cleaning: CleaningPipeline, cpu_pool: CpuPool) -> Self {
+        Self { db_loader, cleaning, cpu_pool }
     }
 
-    pub async fn process_file(&self, file_data: Bytes) -> Result<u32, AppError> {
+    pub async fn process_file(&self, workbook: Arc<ParsedWorkbook>, file_name: &str) -> Result<ProcessingReport, AppError> {
         tracing::info!("Processing Excel file");
-        let cursor = Cursor::new(file_data);
-        
-        let mut workbook: Xlsx<_> = open_workbook_from_rs(cursor)
-            .map_err(|e| AppError::FileProcessingError(format!("Failed to open Excel file: {}", e)))?;
-    
-        let mut total_tabs = 0;
-        let sheet_names = workbook.sheet_names().to_vec();
-        tracing::info!("Processing {} sheets", sheet_names.len());
-    
-        for sheet_name in &sheet_names {
-            tracing::info!("Processing sheet: {}", sheet_name);
-            match workbook.worksheet_range(sheet_name) {
-                Ok(range) => {
-                    let rows: Vec<Vec<Data>> = range.rows().map(|row| row.to_vec()).collect();
-                    
-                    if rows.is_empty() {
-                        tracing::warn!("Sheet {} is empty, skipping", sheet_name);
-                        continue;
-                    }
-    
-                    let mut existing_names = HashSet::new();
-                    let headers = rows.first()
-                        .map(|row| row.iter()
-                            .map(|cell| clean_column_name(&cell.to_string(), &mut existing_names))
-                            .collect::<Vec<_>>())
-                        .unwrap_or_default();
-    
-                    tracing::info!("Creating dataframe for sheet {} with {} rows", sheet_name, rows.len());
-                    match self.create_dataframe(&rows, &headers) {
-                        Ok(mut df) => {
-                            if let Some(cleaned_df) = self.clean_dataframe(&df) {
-                                df = cleaned_df;
-                                
-                                // Detect and normalize date columns
-                                let date_columns = self.detect_date_columns(&df);
-                                df = self.normalize_date_columns(&mut df, &date_columns);
-    
-                                // Generate a unique table name
-                                let table_name = format!("excel_{}_{}", clean_table_name(sheet_name), chrono::Utc::now().timestamp());
-                                tracing::info!("Loading sheet {} into table {}", sheet_name, table_name);
-                                
-                                // Load the data into SQLite
-                                match self.db_loader.load_dataframe(df, &table_name).await {
-                                    Ok(()) => {
-                                        total_tabs += 1;
-                                        tracing::info!("Successfully loaded sheet {} into database", sheet_name);
-                                    }
-                                    Err(e) => {
-                                        tracing::error!("Failed to load sheet {} into database: {}", sheet_name, e);
-                                    }
-                                }
-                            } else {
-                                tracing::warn!("Sheet {} produced empty dataframe after cleaning", sheet_name);
-                            }
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed to create dataframe for sheet {}: {}", sheet_name, e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to read worksheet {}: {}", sheet_name, e);
-                    continue;
-                }
+
+        // Dataframe construction and cleaning are CPU-bound, so they run on
+        // the CPU pool with one rayon task per sheet
+        let processor = self.clone();
+        let prepared = self.cpu_pool.run(move || Ok(processor.prepare_sheets(&workbook))).await?;
+
+        // SQLite writes go through a single connection, one sheet at a time
+        let mut report = ProcessingReport::default();
+        for sheet in prepared {
+            let sheet_report = match sheet {
+                DecodedSheet::Ready(prepared) => self.load_sheet(file_name, prepared).await,
+                DecodedSheet::Finished(report) => report,
+            };
+
+            if sheet_report.status == SheetStatus::Loaded {
+                report.tables_created += 1;
             }
+            report.index_ms += sheet_report.index_ms;
+            report.sheets.push(sheet_report);
         }
     
-        if total_tabs == 0 {
+        if report.tables_created > 1 {
+            match self.db_loader.create_union_views(file_name, &file_view_name(file_name)).await {
+                Ok(views) => report.views = views,
+                Err(e) => tracing::warn!("Failed to create views over matching sheets: {}", e),
+            }
+        }
+
+        // Keys and joins are inferred over every loaded table, including
+        // those from earlier files
+        if report.tables_created > 0 {
+            let new_tables: Vec<String> = report.sheets.iter()
+                .filter_map(|sheet| sheet.table_name.clone())
+                .collect();
+            match self.db_loader.infer_relationships(&new_tables).await {
+                Ok(relationships) => tracing::info!("Inferred {} relationships", relationships.len()),
+                Err(e) => tracing::warn!("Failed to infer relationships: {}", e),
+            }
+        }
+
+        if report.tables_created == 0 {
             tracing::error!("No valid data found in Excel file after processing all sheets");
-            Err(AppError::FileProcessingError("No valid data found in Excel file".to_string()))
+            let reasons = report.sheets.iter()
+                .map(|sheet| format!("{}: {}", sheet.sheet_name, sheet.reason()))
+                .collect::<Vec<_>>()
+                .join("; ");
+            Err(AppError::FileProcessingError(format!("No valid data found in Excel file ({})", reasons)))
         } else {
-            tracing::info!("Successfully processed {} sheets", total_tabs);
-            Ok(total_tabs)
+            tracing::info!("Successfully processed {} sheets", report.tables_created);
+            Ok(report)
+        }
+    }
+
+    /// Builds every sheet's dataframe in parallel, keeping workbook order.
+    fn prepare_sheets(&self, workbook: &ParsedWorkbook) -> Vec<DecodedSheet> {
+        workbook.sheets.par_iter()
+            .map(|sheet| self.prepare_sheet(sheet))
+            .collect()
+    }
+
+    fn prepare_sheet(&self, sheet: &ParsedSheet) -> DecodedSheet {
+        tracing::info!("Processing sheet: {}", sheet.name);
+        let sheet_name = sheet.name.as_str();
+        let mut report = SheetReport::new(sheet_name);
+
+        if let Some(error) = &sheet.error {
+            return DecodedSheet::Finished(report.failed(error.clone()));
+        }
+        let rows = &sheet.rows;
+        if rows.is_empty() {
+            tracing::warn!("Sheet {} is empty, skipping", sheet_name);
+            return DecodedSheet::Finished(report.skipped("Sheet is empty"));
+        }
+        report.rows_in = rows.len() - 1;
+
+        tracing::info!("Creating dataframe for sheet {} with {} rows", sheet_name, rows.len());
+        let df = match self.create_dataframe(sheet) {
+            Ok((df, coercions)) => {
+                report.type_coercions = coercions;
+                df
+            }
+            Err(e) => {
+                tracing::error!("Failed to create dataframe for sheet {}: {}", sheet_name, e);
+                return DecodedSheet::Finished(report.failed(format!("Failed to create dataframe: {}", e)));
+            }
+        };
+
+        let (cleaned, steps) = match self.clean_dataframe(df) {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::error!("Failed to clean dataframe for sheet {}: {}", sheet_name, e);
+                return DecodedSheet::Finished(report.failed(format!("Failed to clean dataframe: {}", e)));
+            }
+        };
+        report.dropped_columns = steps.iter()
+            .flat_map(|step| step.columns_removed.iter().cloned())
+            .collect();
+        report.cleaning = steps;
+
+        let Some(mut df) = cleaned else {
+            tracing::warn!("Sheet {} produced empty dataframe after cleaning", sheet_name);
+            return DecodedSheet::Finished(report.skipped("No rows left after cleaning"));
+        };
+
+        // Detect and normalize date columns
+        let date_columns = self.detect_date_columns(&df);
+        df = self.normalize_date_columns(&mut df, &date_columns, &mut report);
+        report.rows_out = df.height();
+
+        let removed = report.rows_in - report.rows_out;
+        if removed > 0 {
+            report.warnings.push(format!("{} of {} rows removed during cleaning", removed, report.rows_in));
         }
+
+        DecodedSheet::Ready(PreparedSheet {
+            report,
+            cell_range: sheet.cell_range.clone(),
+            headers: sheet.columns.clone(),
+            original_headers: sheet.headers.clone(),
+            df,
+        })
     }
 
-    fn clean_dataframe(&self, df: &DataFrame) -> Option<DataFrame> {
+    async fn load_sheet(&self, file_name: &str, sheet: PreparedSheet) -> SheetReport {
+        let PreparedSheet { mut report, cell_range, headers, original_headers, df } = sheet;
+        let sheet_name = report.sheet_name.clone();
+
+        let base_name = sheet_table_name(file_name, &sheet_name);
+        let table_name = match self.db_loader.reserve_table_name(file_name, &sheet_name, &base_name).await {
+            Ok(table_name) => table_name,
+            Err(e) => return report.failed(format!("Failed to reserve table name: {}", e)),
+        };
+        tracing::info!("Loading sheet {} into table {}", sheet_name, table_name);
+
+        let original_by_column: HashMap<&str, &str> = headers.iter()
+            .map(String::as_str)
+            .zip(original_headers.iter().map(String::as_str))
+            .collect();
+        report.columns = df.get_column_names()
+            .into_iter()
+            .map(|column_name| ColumnMapping {
+                sheet_name: sheet_name.clone(),
+                table_name: table_name.clone(),
+                original_header: original_by_column.get(column_name).unwrap_or(&column_name).to_string(),
+                column_name: column_name.to_string(),
+                inferred_type: report.type_coercions.iter()
+                    .rev()
+                    .find(|c| c.column == column_name)
+                    .map_or("text", |c| c.target_type.as_str())
+                    .to_string(),
+            })
+            .collect();
+
+        let entry = CatalogEntry {
+            table_name: table_name.clone(),
+            file_name: file_name.to_string(),
+            sheet_name: sheet_name.clone(),
+            cell_range,
+            row_count: df.height(),
+            loaded_at: chrono::Utc::now().to_rfc3339(),
+            primary_key: None,
+        };
+
+        // Load the data into SQLite
+        let loaded = match self.db_loader.load_dataframe(df, &table_name).await {
+            Ok(()) => match self.db_loader.save_column_mappings(report.columns.clone()).await {
+                Ok(()) => self.db_loader.register_table(entry).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match loaded {
+            Ok(()) => {
+                tracing::info!("Successfully loaded sheet {} into database", sheet_name);
+                if let Err(e) = self.db_loader.build_lookups(&table_name).await {
+                    tracing::warn!("Failed to build value dictionary for {}: {}", table_name, e);
+                    report.warnings.push(format!("Failed to build value dictionary: {}", e));
+                }
+                // Missing indexes only make queries slower, so they don't fail the sheet
+                match self.db_loader.create_indexes(&table_name).await {
+                    Ok(indexes) => {
+                        if indexes.budget_exhausted {
+                            report.warnings.push("Index time budget ran out; some columns were not indexed".to_string());
+                        }
+                        report.indexes = indexes.indexes;
+                        report.index_ms = indexes.elapsed_ms;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to index {}: {}", table_name, e);
+                        report.warnings.push(format!("Failed to create indexes: {}", e));
+                    }
+                }
+                report.table_name = Some(table_name);
+                report.status = SheetStatus::Loaded;
+                report
+            }
+            Err(e) => {
+                tracing::error!("Failed to load sheet {} into database: {}", sheet_name, e);
+                report.failed(format!("Failed to load into database: {}", e))
+            }
+        }
+    }
+
+    fn clean_dataframe(&self, df: DataFrame) -> Result<(Option<DataFrame>, Vec<StepReport>), AppError> {
         if df.height() == 0 || df.width() == 0 {
-            return None;
+            return Ok((None, Vec::new()));
         }
-    
-        // Drop rows where all values are null or empty
-        let df = df.drop_nulls::<String>(None)
-            .unwrap_or_else(|_| df.clone());
-    
-        // Drop columns where all values are null
-        let df = df.select(
-            df.get_columns()
-                .iter()
-                .filter(|series| !series.is_empty() && !series.is_null().all())
-                .map(|series| series.name())
-                .collect::<Vec<_>>()
-        ).unwrap_or_else(|_| df.clone());
-    
+
+        let (df, steps) = self.cleaning.run(df)?;
+
         if df.height() == 0 || df.width() == 0 {
-            None
+            Ok((None, steps))
         } else {
-            Some(df)
+            Ok((Some(df), steps))
         }
     }
 
-    fn create_dataframe(&self, rows: &[Vec<Data>], headers: &[String]) -> Result<DataFrame, AppError> {
-        if rows.is_empty() || headers.is_empty() {
+    fn create_dataframe(&self, sheet: &ParsedSheet) -> Result<(DataFrame, Vec<TypeCoercion>), AppError> {
+        let rows = &sheet.rows;
+        if rows.is_empty() || sheet.columns.is_empty() {
             return Err(AppError::InvalidInput("Empty data or headers".to_string()));
         }
     
         let mut columns = Vec::new();
+        let mut coercions = Vec::new();
         
-        for (col_idx, header) in headers.iter().enumerate() {
+        for (col_idx, (header, column_type)) in sheet.columns.iter().zip(&sheet.column_types).enumerate() {
             let values: Vec<Data> = rows.iter()
                 .skip(1) // Skip header row
                 .map(|row| row.get(col_idx).cloned().unwrap_or(Data::Empty))
                 .collect();
             
-            let series = match detect_column_type(&values) {
-                t if t == "numeric" => {
+            let series = match *column_type {
+                "numeric" => {
                     let nums: Vec<Option<f64>> = values.iter().map(|v| match v {
                         Data::Float(f) => Some(*f),
                         Data::Int(i) => Some(*i as f64),
                         _ => None,
                     }).collect();
+                    coercions.push(TypeCoercion::new(header, "numeric", &values, &nums));
                     Series::new(header, nums)
                 },
-                t if t == "date" => {
+                "date" => {
                     let dates: Vec<Option<i64>> = values.iter().map(|v| match v {
                         Data::DateTime(d) => {
                             let days_since_1900 = d.as_f64();
@@ -153,6 +296,7 @@ impl ExcelProcessor {
                         },
                         _ => None,
                     }).collect();
+                    coercions.push(TypeCoercion::new(header, "date", &values, &dates));
                     Series::new(header, dates)
                 },
                 _ => {
@@ -164,8 +308,9 @@ impl ExcelProcessor {
             columns.push(series);
         }
         
-        DataFrame::new(columns)
-            .map_err(|e| AppError::InvalidInput(format!("Failed to create DataFrame: {}", e)))
+        let df = DataFrame::new(columns)
+            .map_err(|e| AppError::InvalidInput(format!("Failed to create DataFrame: {}", e)))?;
+        Ok((df, coercions))
     }
 
     fn detect_date_columns(&self, df: &DataFrame) -> Vec<String> {
@@ -194,14 +339,27 @@ impl ExcelProcessor {
             .collect()
     }
 
-    fn normalize_date_columns(&self, df: &mut DataFrame, date_columns: &[String]) -> DataFrame {
+    fn normalize_date_columns(&self, df: &mut DataFrame, date_columns: &[String], report: &mut SheetReport) -> DataFrame {
         for col_name in date_columns {
             if let Ok(series) = df.column(col_name) {
+                let nulls_before = series.null_count();
                 if let Ok(dates) = series.cast(&DataType::Datetime(TimeUnit::Microseconds, None)) {
+                    report.type_coercions.push(TypeCoercion {
+                        column: col_name.clone(),
+                        target_type: "datetime".to_string(),
+                        cells_nulled: dates.null_count().saturating_sub(nulls_before),
+                    });
                     let _ = df.replace(col_name, dates);
                 }
             }
         }
+
+        for coercion in report.type_coercions.iter().filter(|c| c.cells_nulled > 0) {
+            report.warnings.push(format!(
+                "{} value(s) in column {} could not be read as {} and were set to null",
+                coercion.cells_nulled, coercion.column, coercion.target_type
+            ));
+        }
         df.clone()
     }
 }
\ No newline at end of file
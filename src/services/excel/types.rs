@@ -1,9 +1,13 @@
 use smallvec::SmallVec;
 use polars::prelude::DataFrame;
+use serde::Serialize;
+use calamine::Data;
+use super::cleaning::StepReport;
+use crate::services::db_loader::{ColumnMapping, CreatedIndex, UnionView};
 
 pub const SAMPLE_SIZE: usize = 3;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ColumnInfo {
     pub name: String,
     pub data_type: String,
@@ -15,7 +19,7 @@ pub struct ColumnInfo {
     pub has_duplicates: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SheetAnalysis {
     pub sheet_names: Vec<String>,
     pub row_count: usize,
@@ -26,4 +30,104 @@ pub struct SheetAnalysis {
     pub date_columns: Vec<String>,
     pub numeric_columns: Vec<String>,
     pub text_columns: Vec<String>,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SheetStatus {
+    Loaded,
+    Skipped,
+    Failed,
+}
+
+/// A column converted from raw cells to a typed column. `cells_nulled` counts
+/// non-empty cells that did not fit the type and were loaded as NULL.
+#[derive(Debug, Serialize)]
+pub struct TypeCoercion {
+    pub column: String,
+    pub target_type: String,
+    pub cells_nulled: usize,
+}
+
+impl TypeCoercion {
+    pub fn new<T>(column: &str, target_type: &str, raw: &[Data], converted: &[Option<T>]) -> Self {
+        let cells_nulled = raw.iter()
+            .zip(converted)
+            .filter(|(cell, value)| !matches!(cell, Data::Empty) && value.is_none())
+            .count();
+        Self {
+            column: column.to_string(),
+            target_type: target_type.to_string(),
+            cells_nulled,
+        }
+    }
+}
+
+/// Outcome of processing a single sheet, returned to the client so a missing
+/// tab can be traced back to the step that dropped it.
+#[derive(Debug, Serialize)]
+pub struct SheetReport {
+    pub sheet_name: String,
+    pub status: SheetStatus,
+    pub table_name: Option<String>,
+    pub rows_in: usize,
+    pub rows_out: usize,
+    pub dropped_columns: Vec<String>,
+    pub type_coercions: Vec<TypeCoercion>,
+    pub columns: Vec<ColumnMapping>,
+    pub cleaning: Vec<StepReport>,
+    /// Indexes built after loading, and the time spent choosing and building them.
+    pub indexes: Vec<CreatedIndex>,
+    pub index_ms: f64,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl SheetReport {
+    pub fn new(sheet_name: &str) -> Self {
+        Self {
+            sheet_name: sheet_name.to_string(),
+            status: SheetStatus::Skipped,
+            table_name: None,
+            rows_in: 0,
+            rows_out: 0,
+            dropped_columns: Vec::new(),
+            type_coercions: Vec::new(),
+            columns: Vec::new(),
+            cleaning: Vec::new(),
+            indexes: Vec::new(),
+            index_ms: 0.0,
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn skipped(mut self, reason: &str) -> Self {
+        self.status = SheetStatus::Skipped;
+        self.warnings.push(reason.to_string());
+        self
+    }
+
+    pub fn failed(mut self, error: String) -> Self {
+        self.status = SheetStatus::Failed;
+        self.error = Some(error);
+        self
+    }
+
+    /// Short explanation of why the sheet was not loaded.
+    pub fn reason(&self) -> &str {
+        self.error.as_deref()
+            .or_else(|| self.warnings.last().map(String::as_str))
+            .unwrap_or("loaded")
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ProcessingReport {
+    pub tables_created: u32,
+    /// Total time spent building indexes across all sheets.
+    pub index_ms: f64,
+    pub sheets: Vec<SheetReport>,
+    /// Views created over sheets that share their columns.
+    pub views: Vec<UnionView>,
+}
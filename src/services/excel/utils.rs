@@ -1,66 +1,162 @@
 use std::collections::HashSet;
-use bytes::Bytes;
-use reqwest::Client;
-use crate::error::AppError;
 use chrono::NaiveDateTime;
 use calamine::Data;
 
-pub fn clean_column_name(name: &str, existing_names: &mut HashSet<String>) -> String {
-    let base_name = name
-    .chars()
-    .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
-    .collect::<String>()
-    .to_lowercase();
-
-let mut cleaned = if base_name.chars().next().map_or(true, |c| !c.is_alphabetic()) {
-    format!("col_{}", base_name)
-} else {
-    base_name
-};
-
-// If the name already exists, add a numeric suffix
-let mut counter = 1;
-let original_name = cleaned.clone();
-while !existing_names.insert(cleaned.clone()) {
-    cleaned = format!("{}_{}", original_name, counter);
-    counter += 1;
+/// Longest identifier we generate, suffix included.
+pub const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// SQLite keywords (https://www.sqlite.org/lang_keywords.html). Identifiers that
+/// collide with one of these get a prefix so generated SQL never depends on quoting.
+const SQLITE_KEYWORDS: &[&str] = &[
+    "abort", "action", "add", "after", "all", "alter", "always", "analyze", "and", "as",
+    "asc", "attach", "autoincrement", "before", "begin", "between", "by", "cascade", "case",
+    "cast", "check", "collate", "column", "commit", "conflict", "constraint", "create",
+    "cross", "current", "current_date", "current_time", "current_timestamp", "database",
+    "default", "deferrable", "deferred", "delete", "desc", "detach", "distinct", "do", "drop",
+    "each", "else", "end", "escape", "except", "exclude", "exclusive", "exists", "explain",
+    "fail", "filter", "first", "following", "for", "foreign", "from", "full", "generated",
+    "glob", "group", "groups", "having", "if", "ignore", "immediate", "in", "index", "indexed",
+    "initially", "inner", "insert", "instead", "intersect", "into", "is", "isnull", "join",
+    "key", "last", "left", "like", "limit", "match", "materialized", "natural", "no", "not",
+    "nothing", "notnull", "null", "nulls", "of", "offset", "on", "or", "order", "others",
+    "outer", "over", "partition", "plan", "pragma", "preceding", "primary", "query", "raise",
+    "range", "recursive", "references", "regexp", "reindex", "release", "rename", "replace",
+    "restrict", "returning", "right", "rollback", "row", "rows", "savepoint", "select", "set",
+    "table", "temp", "temporary", "then", "ties", "to", "transaction", "trigger", "unbounded",
+    "union", "unique", "update", "using", "vacuum", "values", "view", "virtual", "when",
+    "where", "window", "with", "without",
+];
+
+pub fn is_reserved_word(name: &str) -> bool {
+    SQLITE_KEYWORDS.contains(&name.to_ascii_lowercase().as_str())
 }
 
-cleaned
+/// Maps common Latin accented letters to their ASCII base (ç→c, ã→a).
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'á' | 'à' | 'â' | 'ã' | 'ä' | 'å' => "a",
+        'é' | 'è' | 'ê' | 'ë' => "e",
+        'í' | 'ì' | 'î' | 'ï' => "i",
+        'ó' | 'ò' | 'ô' | 'õ' | 'ö' | 'ø' => "o",
+        'ú' | 'ù' | 'û' | 'ü' => "u",
+        'ç' => "c",
+        'ñ' => "n",
+        'ý' | 'ÿ' => "y",
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        'ª' => "a",
+        'º' => "o",
+        _ => return None,
+    })
 }
 
-pub fn clean_table_name(name: &str) -> String {
-    let cleaned = name
-    .chars()
-    .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
-    .collect::<String>()
-    .to_lowercase();
-
-if cleaned.chars().next().map_or(true, |c| !c.is_alphabetic()) {
-    format!("tbl_{}", cleaned)
-} else {
+/// Lowercases and transliterates accented letters, keeping everything else
+/// (`São Paulo` → `sao paulo`).
+pub fn fold_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.to_lowercase().chars() {
+        match transliterate(c) {
+            Some(ascii) => out.push_str(ascii),
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+/// Lowercases, transliterates and replaces anything outside `[a-z0-9_]` with
+/// underscores, collapsing runs of them.
+pub fn to_ascii_identifier(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c);
+        } else if let Some(ascii) = transliterate(c) {
+            out.push_str(ascii);
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    out.trim_matches('_').to_string()
+}
+
+fn make_identifier(name: &str, prefix: &str) -> String {
+    let base = to_ascii_identifier(name);
+    let needs_prefix = base.chars().next().map_or(true, |c| !c.is_ascii_alphabetic())
+        || is_reserved_word(&base);
+
+    let mut identifier = if base.is_empty() {
+        prefix.to_string()
+    } else if needs_prefix {
+        format!("{}_{}", prefix, base)
+    } else {
+        base
+    };
+    identifier.truncate(MAX_IDENTIFIER_LEN);
+    identifier
+}
+
+/// Appends `_1`, `_2`, ... until `name` is not in `existing_names`, truncating
+/// the base so the result stays within `MAX_IDENTIFIER_LEN`.
+pub fn dedupe_identifier(name: String, existing_names: &mut HashSet<String>) -> String {
+    let mut cleaned = name.clone();
+    let mut counter = 1;
+    while !existing_names.insert(cleaned.clone()) {
+        let suffix = format!("_{}", counter);
+        let mut base = name.clone();
+        base.truncate(MAX_IDENTIFIER_LEN - suffix.len());
+        cleaned = format!("{}{}", base, suffix);
+        counter += 1;
+    }
     cleaned
 }
+
+pub fn clean_column_name(name: &str, existing_names: &mut HashSet<String>) -> String {
+    dedupe_identifier(make_identifier(name, "col"), existing_names)
+}
+
+pub fn clean_table_name(name: &str) -> String {
+    make_identifier(name, "tbl")
 }
 
-pub async fn load_file_from_url(url: &str) -> Result<Bytes, AppError> {
-    let client = Client::new();
-    let response = client
-        .get(url)
-        .send()
-        .await
-        .map_err(|e| AppError::FileProcessingError(format!("Failed to fetch file: {}", e)))?;
-
-    if !response.status().is_success() {
-        return Err(AppError::FileProcessingError(
-            format!("Failed to fetch file. Status: {}", response.status())
-        ));
+/// Spreadsheet column letters for a zero-based index (0 → A, 26 → AA).
+pub fn column_letter(mut col: u32) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push(b'A' + (col % 26) as u8);
+        if col < 26 {
+            break;
+        }
+        col = col / 26 - 1;
     }
+    letters.iter().rev().map(|&b| b as char).collect()
+}
+
+/// A1-style range for zero-based (row, column) corners, e.g. `A1:F120`.
+pub fn cell_range(start: (u32, u32), end: (u32, u32)) -> String {
+    format!(
+        "{}{}:{}{}",
+        column_letter(start.1), start.0 + 1,
+        column_letter(end.1), end.0 + 1
+    )
+}
+
+/// Base table name for a sheet: the file name without its extension, then the
+/// sheet name, e.g. `vendas_2024_janeiro` for "Vendas 2024.xlsx" / "Janeiro".
+pub fn sheet_table_name(file_name: &str, sheet_name: &str) -> String {
+    let file_stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    clean_table_name(&format!("{} {}", file_stem, sheet_name))
+}
 
-    response
-        .bytes()
-        .await
-        .map_err(|e| AppError::FileProcessingError(format!("Failed to read response bytes: {}", e)))
+/// Base name for views over several of a file's sheets: the file name
+/// without its extension, e.g. `vendas_2024` for "Vendas 2024.xlsx".
+pub fn file_view_name(file_name: &str) -> String {
+    let file_stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    clean_table_name(file_stem)
 }
 
 pub fn update_min_max(min_max: &mut (Option<String>, Option<String>), value: &str) {
@@ -114,12 +210,12 @@ pub fn is_date_string(s: &str) -> bool {
     false
 }
 
-pub fn detect_column_type(values: &[Data]) -> &'static str {
+pub fn detect_column_type<'a>(values: impl IntoIterator<Item = &'a Data>) -> &'static str {
     let mut numeric_count = 0;
     let mut date_count = 0;
     let mut total_count = 0;
 
-    for value in values.iter().filter(|v| !matches!(v, Data::Empty)) {
+    for value in values.into_iter().filter(|v| !matches!(v, Data::Empty)) {
         total_count += 1;
         match value {
             Data::Float(_) | Data::Int(_) => numeric_count += 1,
@@ -0,0 +1,115 @@
+use super::utils::*;
+use std::collections::HashSet;
+use std::io::Cursor;
+use bytes::Bytes;
+use calamine::{Data, Range, Xlsx, open_workbook_from_rs, Reader};
+use rayon::prelude::*;
+use crate::error::AppError;
+
+/// One worksheet, decoded.
+#[derive(Debug)]
+pub struct ParsedSheet {
+    pub name: String,
+    pub cell_range: Option<String>,
+    /// Decoded cells, header row first.
+    pub rows: Vec<Vec<Data>>,
+    /// Header text as written in the sheet.
+    pub headers: Vec<String>,
+    /// SQL-safe, unique column names, one per header.
+    pub columns: Vec<String>,
+    /// Type inferred for each column from all of its data rows.
+    pub column_types: Vec<&'static str>,
+    /// Set when the worksheet could not be read.
+    pub error: Option<String>,
+}
+
+/// A workbook decoded once and shared by the analyzer and the processor, so
+/// both see exactly the same cells.
+#[derive(Debug)]
+pub struct ParsedWorkbook {
+    pub sheets: Vec<ParsedSheet>,
+}
+
+impl ParsedWorkbook {
+    /// Decodes every sheet, in parallel and in workbook order. This is
+    /// CPU-bound and should run off the async runtime.
+    pub fn parse(file_data: Bytes) -> Result<Self, AppError> {
+        let start = std::time::Instant::now();
+        let sheet_names = open_workbook(&file_data)?.sheet_names().to_vec();
+        tracing::info!("Parsing {} sheets: {:?}", sheet_names.len(), sheet_names);
+
+        // calamine readers need `&mut` access, so each task opens its own
+        // reader over the shared bytes and decodes its sheet's XML itself
+        let sheets = sheet_names.into_par_iter()
+            .map(|sheet_name| {
+                let range = open_workbook(&file_data)
+                    .map_err(|e| e.to_string())
+                    .and_then(|mut workbook| workbook.worksheet_range(&sheet_name).map_err(|e| e.to_string()));
+                ParsedSheet::parse(sheet_name, range)
+            })
+            .collect();
+        tracing::info!("Workbook parsed in {:?}", start.elapsed());
+        Ok(Self { sheets })
+    }
+
+    pub fn sheet_names(&self) -> Vec<String> {
+        self.sheets.iter().map(|sheet| sheet.name.clone()).collect()
+    }
+
+    /// Rough in-memory size, used to weigh cache entries.
+    pub fn approx_bytes(&self) -> usize {
+        self.sheets.iter()
+            .flat_map(|sheet| sheet.rows.iter().flatten())
+            .map(|cell| std::mem::size_of::<Data>() + match cell {
+                Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => s.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// Opens a reader over the file. `Bytes` clones share one buffer, so each
+/// reader costs only the workbook's shared strings and sheet index.
+fn open_workbook(file_data: &Bytes) -> Result<Xlsx<Cursor<Bytes>>, AppError> {
+    open_workbook_from_rs(Cursor::new(file_data.clone()))
+        .map_err(|e| AppError::FileProcessingError(format!("Failed to open Excel file: {}", e)))
+}
+
+impl ParsedSheet {
+    fn parse(sheet_name: String, range: Result<Range<Data>, String>) -> Self {
+        let mut sheet = ParsedSheet {
+            name: sheet_name,
+            cell_range: None,
+            rows: Vec::new(),
+            headers: Vec::new(),
+            columns: Vec::new(),
+            column_types: Vec::new(),
+            error: None,
+        };
+
+        let range = match range {
+            Ok(range) => range,
+            Err(e) => {
+                tracing::warn!("Failed to read worksheet {}: {}", sheet.name, e);
+                sheet.error = Some(format!("Failed to read worksheet: {}", e));
+                return sheet;
+            }
+        };
+
+        sheet.cell_range = range.start().zip(range.end()).map(|(start, end)| cell_range(start, end));
+        sheet.rows = range.rows().map(|row| row.to_vec()).collect();
+        drop(range);
+
+        sheet.headers = sheet.rows.first()
+            .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+            .unwrap_or_default();
+        let mut existing_names = HashSet::new();
+        sheet.columns = sheet.headers.iter()
+            .map(|header| clean_column_name(header, &mut existing_names))
+            .collect();
+        sheet.column_types = (0..sheet.columns.len())
+            .map(|idx| detect_column_type(sheet.rows.iter().skip(1).filter_map(|row| row.get(idx))))
+            .collect();
+        sheet
+    }
+}
@@ -1,27 +1,81 @@
 use bytes::Bytes;
 use crate::error::AppError;
 use crate::services::{
-    excel::{ExcelAnalyzer, ExcelProcessor, types::*},
+    cpu_pool::CpuPool,
+    excel::{CleaningPipeline, ExcelAnalyzer, ExcelProcessor, ParsedWorkbook, types::*},
     db_loader::DbLoader,
 };
 use std::sync::Arc;
 use std::time::Duration;
-use reqwest::Client;
+use moka::sync::Cache;
+use reqwest::{header, Client, StatusCode};
+use sha2::{Digest, Sha256};
 use tokio::time::sleep;
 use tracing::{info, warn};
 use tokio::sync::OnceCell;
-use lru::LruCache;
-use std::sync::Mutex;
-use std::num::NonZeroUsize;
 
 // Constants for configuration
 const MAX_RETRIES: u32 = 3;
-const CACHE_MAX_CAPACITY: usize = 100;
 const REQUEST_TIMEOUT_SECS: u64 = 30;
+/// Total size of downloaded files kept in memory.
+const FILE_CACHE_MAX_BYTES: u64 = 256 * 1024 * 1024;
+/// Approximate total size of parsed workbooks kept in memory.
+const WORKBOOK_CACHE_MAX_BYTES: u64 = 512 * 1024 * 1024;
+const ANALYSIS_CACHE_CAPACITY: u64 = 256;
+const VALIDATOR_CACHE_CAPACITY: u64 = 10_000;
+const CACHE_TTI: Duration = Duration::from_secs(3600);
+
+/// A downloaded file and the SHA-256 of its contents, which keys every cache
+/// derived from it.
+#[derive(Debug, Clone)]
+pub struct FileContent {
+    pub hash: String,
+    pub data: Bytes,
+    /// ETag the server sent with these contents.
+    pub etag: Option<String>,
+}
+
+impl FileContent {
+    pub fn version(&self) -> FileVersion {
+        FileVersion { hash: self.hash.clone(), etag: self.etag.clone() }
+    }
+}
+
+/// Which contents of a file a copy held elsewhere has, e.g. a file loaded
+/// into a session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileVersion {
+    pub hash: String,
+    pub etag: Option<String>,
+}
+
+pub enum FileFetch {
+    /// The server confirmed the held copy is current; nothing was downloaded.
+    Unchanged(FileVersion),
+    Fetched(FileContent),
+}
+
+/// Validator from the last download of a URL, used for conditional GETs.
+#[derive(Debug, Clone)]
+struct UrlValidator {
+    etag: String,
+    hash: String,
+}
+
+enum Download {
+    NotModified,
+    Fetched { data: Bytes, etag: Option<String> },
+}
 
 pub struct FileProcessor {
     client: Client,
-    file_cache: Arc<Mutex<LruCache<String, Bytes>>>,
+    /// File contents by content hash, bounded by total bytes.
+    file_cache: Cache<String, Bytes>,
+    /// Last ETag seen per URL, keyed without the query string because signed
+    /// URLs change their signature on every request.
+    validators: Cache<String, UrlValidator>,
+    workbooks: Cache<String, Arc<ParsedWorkbook>>,
+    analyses: Cache<String, SheetAnalysis>,
 }
 
 impl FileProcessor {
@@ -31,48 +85,115 @@ impl FileProcessor {
             .build()
             .map_err(|e| AppError::FileProcessingError(format!("Failed to create HTTP client: {}", e)))?;
     
-        let cache_capacity = NonZeroUsize::new(CACHE_MAX_CAPACITY)
-            .ok_or_else(|| AppError::FileProcessingError("Invalid cache capacity".to_string()))?;
-        let file_cache = Arc::new(Mutex::new(LruCache::new(cache_capacity)));
+        let file_cache = Cache::builder()
+            .max_capacity(FILE_CACHE_MAX_BYTES)
+            .weigher(|_, data: &Bytes| u32::try_from(data.len()).unwrap_or(u32::MAX))
+            .time_to_idle(CACHE_TTI)
+            .build();
+        let workbooks = Cache::builder()
+            .max_capacity(WORKBOOK_CACHE_MAX_BYTES)
+            .weigher(|_, workbook: &Arc<ParsedWorkbook>| u32::try_from(workbook.approx_bytes()).unwrap_or(u32::MAX))
+            .time_to_idle(CACHE_TTI)
+            .build();
+        let analyses = Cache::builder()
+            .max_capacity(ANALYSIS_CACHE_CAPACITY)
+            .time_to_idle(CACHE_TTI)
+            .build();
+        let validators = Cache::builder()
+            .max_capacity(VALIDATOR_CACHE_CAPACITY)
+            .time_to_idle(CACHE_TTI)
+            .build();
     
         Ok(Self {
             client,
             file_cache,
+            validators,
+            workbooks,
+            analyses,
         })
     }
 
-    pub async fn load_file_from_url(&self, url: &str) -> Result<Bytes, AppError> {
-        // Check cache first
-        if let Some(cached_data) = self.file_cache.lock()
-            .map_err(|e| AppError::FileProcessingError(format!("Cache lock error: {}", e)))?
-            .get(url) {
-            info!("File found in cache: {}", url);
-            return Ok(cached_data.clone());
-        }
+    /// Downloads the file, unless the caller's `held` copy or the cached one
+    /// is still current. The held copy is revalidated with its own ETag, or
+    /// with the last one seen for the URL if that was for the same contents,
+    /// so it needs no cached bytes.
+    pub async fn load_file_from_url(
+        &self,
+        cpu_pool: &CpuPool,
+        url: &str,
+        held: Option<&FileVersion>,
+    ) -> Result<FileFetch, AppError> {
+        let url_key = url_cache_key(url);
+        let held = held.and_then(|held| {
+            let etag = held.etag.clone().or_else(|| {
+                self.validators.get(&url_key)
+                    .filter(|validator| validator.hash == held.hash)
+                    .map(|validator| validator.etag)
+            })?;
+            Some(FileVersion { hash: held.hash.clone(), etag: Some(etag) })
+        });
+        // Otherwise revalidate with the server if we still hold what it sent last time
+        let mut cached = self.validators.get(&url_key).and_then(|validator| {
+            self.file_cache.get(&validator.hash).map(|data| (validator, data))
+        });
 
         let mut retries = 0;
         let mut last_error = None;
 
         while retries < MAX_RETRIES {
-            match self.attempt_file_download(url).await {
-                Ok(file_data) => {
-                    // Cache the successful result
-                    if let Ok(mut cache) = self.file_cache.lock() {
-                        cache.put(url.to_string(), file_data.clone());
+            let etag = match &held {
+                Some(held) => held.etag.as_deref(),
+                None => cached.as_ref().map(|(validator, _)| validator.etag.as_str()),
+            };
+            match self.attempt_file_download(url, etag).await {
+                Ok(Download::NotModified) => {
+                    if let Some(held) = held {
+                        info!("File not modified since it was loaded: {}", url_key);
+                        return Ok(FileFetch::Unchanged(held));
                     }
-                    return Ok(file_data);
+                    match cached.take() {
+                        Some((validator, data)) => {
+                            info!("File not modified, using cached content: {}", url_key);
+                            return Ok(FileFetch::Fetched(FileContent { hash: validator.hash, data, etag: Some(validator.etag) }));
+                        }
+                        // Nothing to reuse, so the retry asks for the full body
+                        None => {
+                            warn!("Got 304 Not Modified without a cached copy of {}", url_key);
+                            self.validators.invalidate(&url_key);
+                            last_error = Some(AppError::FileProcessingError(
+                                "Server answered 304 Not Modified but no cached copy exists".to_string()
+                            ));
+                        }
+                    }
+                }
+                Ok(Download::Fetched { data, etag }) => {
+                    // Hashing a large file is CPU-bound
+                    let hash = {
+                        let data = data.clone();
+                        cpu_pool.run(move || Ok(content_hash(&data))).await?
+                    };
+                    let file = FileContent { hash, data, etag: etag.clone() };
+                    if self.file_cache.contains_key(&file.hash) {
+                        info!("Downloaded file matches cached content {}", file.hash);
+                    }
+                    self.file_cache.insert(file.hash.clone(), file.data.clone());
+                    match etag {
+                        Some(etag) => self.validators.insert(url_key, UrlValidator { etag, hash: file.hash.clone() }),
+                        None => self.validators.invalidate(&url_key),
+                    }
+                    return Ok(FileFetch::Fetched(file));
                 }
                 Err(e) => {
-                    warn!("Attempt {} failed to download file {}: {}", retries + 1, url, e);
+                    warn!("Attempt {} failed to download file {}: {}", retries + 1, url_key, e);
                     last_error = Some(e);
-                    retries += 1;
-
-                    if retries < MAX_RETRIES {
-                        let delay = Duration::from_secs(2u64.pow(retries));
-                        sleep(delay).await;
-                    }
                 }
             }
+            retries += 1;
+
+            if retries < MAX_RETRIES {
+                let delay = Duration::from_secs(2u64.pow(retries));
+                sleep(delay).await;
+            }
         }
 
         Err(last_error.unwrap_or_else(|| {
@@ -80,49 +201,155 @@ impl FileProcessor {
         }))
     }
 
-    async fn attempt_file_download(&self, url: &str) -> Result<Bytes, AppError> {
+    /// Drops a file's contents, parsed workbook and analysis, and the URL
+    /// validators pointing at it. Returns whether anything was cached.
+    pub fn forget(&self, hash: &str) -> bool {
+        let mut found = self.file_cache.remove(hash).is_some();
+        found |= self.workbooks.remove(hash).is_some();
+        found |= self.analyses.remove(hash).is_some();
+        for (url_key, validator) in self.validators.iter() {
+            if validator.hash == hash {
+                self.validators.invalidate(url_key.as_str());
+            }
+        }
+        found
+    }
+
+    async fn attempt_file_download(&self, url: &str, etag: Option<&str>) -> Result<Download, AppError> {
         info!("Downloading file from URL: {}", url);
         
-        let response = self.client
-            .get(url)
+        let mut request = self.client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        let response = request
             .send()
             .await
             .map_err(|e| AppError::FileProcessingError(format!("Failed to download file: {}", e)))?;
 
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Download::NotModified);
+        }
         if !response.status().is_success() {
             return Err(AppError::FileProcessingError(
                 format!("Failed to download file. Status: {}", response.status())
             ));
         }
 
-        response
+        let etag = response.headers()
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let data = response
             .bytes()
             .await
-            .map_err(|e| AppError::FileProcessingError(format!("Failed to read file bytes: {}", e)))
+            .map_err(|e| AppError::FileProcessingError(format!("Failed to read file bytes: {}", e)))?;
+        Ok(Download::Fetched { data, etag })
     }
 }
 
 // Singleton instance for the FileProcessor
 static FILE_PROCESSOR: OnceCell<FileProcessor> = OnceCell::const_new();
 
+async fn file_processor() -> Result<&'static FileProcessor, AppError> {
+    FILE_PROCESSOR
+        .get_or_try_init(|| async { FileProcessor::new() })
+        .await
+        .map_err(|e| AppError::FileProcessingError(format!("Failed to initialize FileProcessor: {}", e)))
+}
+
 // Public interface functions
-pub async fn analyze_excel_file_from_bytes(file_data: Bytes) -> Result<SheetAnalysis, AppError> {
+/// Decodes the workbook once on the CPU pool; the result feeds both
+/// `analyze_workbook` and `process_excel_file`. Files seen before are served
+/// from the cache by content hash.
+pub async fn parse_workbook(cpu_pool: &CpuPool, file: &FileContent) -> Result<Arc<ParsedWorkbook>, AppError> {
+    let processor = file_processor().await?;
+    if let Some(workbook) = processor.workbooks.get(&file.hash) {
+        info!("Using cached workbook {}", file.hash);
+        return Ok(workbook);
+    }
+
+    info!("Parsing Excel workbook");
+    let data = file.data.clone();
+    let workbook = Arc::new(cpu_pool.run(move || ParsedWorkbook::parse(data)).await?);
+    processor.workbooks.insert(file.hash.clone(), workbook.clone());
+    Ok(workbook)
+}
+
+pub async fn analyze_workbook(
+    cpu_pool: &CpuPool,
+    file: &FileContent,
+    workbook: Arc<ParsedWorkbook>,
+) -> Result<SheetAnalysis, AppError> {
+    let processor = file_processor().await?;
+    if let Some(analysis) = processor.analyses.get(&file.hash) {
+        info!("Using cached analysis {}", file.hash);
+        return Ok(analysis);
+    }
+
     info!("Starting Excel file analysis");
-    let analyzer = ExcelAnalyzer;
-    analyzer.analyze_from_bytes(file_data).await
+    let analysis = cpu_pool.run(move || ExcelAnalyzer.analyze(&workbook)).await?;
+    processor.analyses.insert(file.hash.clone(), analysis.clone());
+    Ok(analysis)
 }
 
-pub async fn process_excel_file(file_data: Bytes, db_loader: &DbLoader) -> Result<u32, AppError> {
+pub async fn process_excel_file(
+    cpu_pool: &CpuPool,
+    workbook: Arc<ParsedWorkbook>,
+    file_name: &str,
+    db_loader: &DbLoader,
+    cleaning: CleaningPipeline,
+) -> Result<ProcessingReport, AppError> {
     info!("Starting Excel file processing");
-    let processor = ExcelProcessor::new(db_loader.clone());
-    processor.process_file(file_data).await
+    let processor = ExcelProcessor::new(db_loader.clone(), cleaning, cpu_pool.clone());
+    processor.process_file(workbook, file_name).await
 }
 
-pub async fn load_file_from_url(url: &str) -> Result<Bytes, AppError> {
-    let processor = FILE_PROCESSOR
-        .get_or_try_init(|| async { FileProcessor::new() })
-        .await
-        .map_err(|e| AppError::FileProcessingError(format!("Failed to initialize FileProcessor: {}", e)))?;
-    
-    processor.load_file_from_url(url).await
-}
\ No newline at end of file
+pub async fn load_file_from_url(
+    cpu_pool: &CpuPool,
+    url: &str,
+    held: Option<&FileVersion>,
+) -> Result<FileFetch, AppError> {
+    file_processor().await?.load_file_from_url(cpu_pool, url, held).await
+}
+
+/// Removes everything cached for the file with this content hash.
+pub async fn forget_file(hash: &str) -> Result<bool, AppError> {
+    Ok(file_processor().await?.forget(hash))
+}
+
+/// Hex-encoded SHA-256 of the file contents.
+pub fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Cache key for a URL: scheme, host and path, without query string or fragment.
+fn url_cache_key(url: &str) -> String {
+    url.split(['?', '#']).next().unwrap_or_default().to_string()
+}
+
+/// File name from the last path segment of a (signed) URL, percent-decoded.
+pub fn file_name_from_url(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let segment = path.rsplit('/').next().unwrap_or_default();
+
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -14,13 +14,47 @@ use async_openai::{
 use serde::{Deserialize, Serialize};
 use crate::error::AppError;
 use crate::services::db_loader::DbLoader;
-use rusqlite::types::ValueRef;
-use serde_json::Value as JsonValue;
+use crate::services::sql_guard::{GuardError, ReadOnlyGuard};
+use crate::services::value_dictionary::{LiteralCorrection, ValueDictionary};
+use crate::services::query_runner::{QueryRunner, RowSet};
+use std::time::Duration;
+
+const DEFAULT_SCHEMA_TOKEN_BUDGET: usize = 4000;
+/// Times a query that fails validation is sent back to Teddy for repair.
+const MAX_REPAIR_ATTEMPTS: usize = 2;
+
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AgentResponse {
     pub comment: String,
-    pub queries: Vec<String>,
+    pub queries: Vec<GeneratedQuery>,
+    #[serde(default)]
+    pub literal_corrections: Vec<LiteralCorrection>,
+    #[serde(default)]
+    pub debug_trace: Vec<ValidationAttempt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratedQuery {
+    pub sql: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub explanation: String,
+}
+
+/// One validation of a generated query. Attempt 0 is Teddy's original query;
+/// later attempts are repairs sent back with the SQLite error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationAttempt {
+    pub query_index: usize,
+    pub attempt: usize,
+    pub sql: String,
+    pub error: Option<String>,
+    /// Why asking Teddy to repair this attempt's query failed. The query is
+    /// then kept as it is, with its validation error.
+    #[serde(default)]
+    pub repair_error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -37,22 +71,30 @@ pub struct TeddyJsonObject {
 #[derive(Debug, Serialize)]
 pub struct QueryResult {
     pub comment: String,
-    pub data: Vec<JsonValue>,
+    pub results: Vec<ExecutedQuery>,
+    pub literal_corrections: Vec<LiteralCorrection>,
+    pub debug_trace: Vec<ValidationAttempt>,
+}
+
+/// Outcome of one generated query. A failed query carries its error and no
+/// rows, without affecting the others.
+#[derive(Debug, Serialize)]
+pub struct ExecutedQuery {
+    /// Handle for paging through the full result within the session.
+    pub result_id: String,
+    pub sql: String,
+    pub title: String,
+    pub explanation: String,
+    #[serde(flatten)]
+    pub rows: RowSet,
 }
 
 pub struct LlmAgent {
     client: Client<OpenAIConfig>,
     model: String,
     db_loader: DbLoader,
-}
-
-#[derive(Debug)]
-enum SqlValue {
-    Null,
-    Integer(i64),
-    Float(f64),
-    Text(String),
-    Blob,
+    schema_token_budget: usize,
+    runner: QueryRunner,
 }
 
 impl LlmAgent {
@@ -62,29 +104,123 @@ impl LlmAgent {
         Ok(Self {
             client: Client::with_config(config),
             model: "gpt-4o-mini".to_string(),
+            runner: QueryRunner::new(db_loader.clone()),
             db_loader,
+            schema_token_budget: DEFAULT_SCHEMA_TOKEN_BUDGET,
         })
     }
 
+    pub fn with_schema_token_budget(mut self, schema_token_budget: usize) -> Self {
+        self.schema_token_budget = schema_token_budget;
+        self
+    }
+
+    /// Sets the per-query execution deadline and the number of rows returned
+    /// per query.
+    pub fn with_query_limits(mut self, query_timeout: Duration, max_result_rows: usize) -> Self {
+        self.runner = self.runner.with_limits(query_timeout, max_result_rows);
+        self
+    }
+
     pub async fn generate_analysis(
         &self,
         messages: &[String],
     ) -> Result<AgentResponse, AppError> {        
         // Run Dolores and schema fetch truly in parallel
-        let (dolores_response, schema) = tokio::join!(
+        let (dolores_response, schema, dictionary) = tokio::join!(
             self.call_dolores(messages),
-            self.db_loader.get_schema_with_samples()
+            self.db_loader.get_schema_with_samples(self.schema_token_budget),
+            self.db_loader.get_value_dictionary()
         );
         
         // Handle errors separately to avoid blocking
-        let (dolores_response, schema) = match (dolores_response, schema) {
-            (Ok(d), Ok(s)) => (d, s),
-            (Err(e), _) => return Err(e),
-            (_, Err(e)) => return Err(e),
+        let (dolores_response, schema, dictionary) = match (dolores_response, schema, dictionary) {
+            (Ok(d), Ok(s), Ok(v)) => (d, s, v),
+            (Err(e), _, _) => return Err(e),
+            (_, Err(e), _) => return Err(e),
+            (_, _, Err(e)) => return Err(e),
         };
         
-        let teddy_response = self.call_teddy(&dolores_response.request_for_teddy, &schema).await?;
-        Ok(self.sanitize_values(teddy_response))
+        let request = dolores_response.request_for_teddy;
+        let teddy_response = self.call_teddy(&request, &schema).await?;
+        let response = self.correct_literals(self.sanitize_values(teddy_response), &dictionary);
+        self.validate_and_repair(response, &request, &schema, &dictionary).await
+    }
+
+    /// Prepares every query against the session database (which compiles it
+    /// and applies the read-only guard without running it). Failing queries
+    /// go back to Teddy with the SQLite error, up to `MAX_REPAIR_ATTEMPTS`
+    /// times each; every attempt is recorded in the debug trace.
+    async fn validate_and_repair(
+        &self,
+        response: AgentResponse,
+        request: &str,
+        schema: &str,
+        dictionary: &ValueDictionary,
+    ) -> Result<AgentResponse, AppError> {
+        let guard = self.runner.read_only_guard().await?;
+        let mut literal_corrections = response.literal_corrections;
+        let mut debug_trace = response.debug_trace;
+        let mut queries = Vec::with_capacity(response.queries.len());
+
+        for (query_index, mut query) in response.queries.into_iter().enumerate() {
+            for attempt in 0..=MAX_REPAIR_ATTEMPTS {
+                let error = self.validate_query(&guard, &query.sql).await?;
+                debug_trace.push(ValidationAttempt {
+                    query_index,
+                    attempt,
+                    sql: query.sql.clone(),
+                    error: error.clone(),
+                    repair_error: None,
+                });
+                let Some(error) = error else { break };
+                tracing::warn!("Query {} failed validation (attempt {}): {}", query_index, attempt, error);
+                if attempt == MAX_REPAIR_ATTEMPTS {
+                    break;
+                }
+
+                // A failed repair call leaves this query as it is instead of
+                // failing the whole analysis
+                let repaired = match self.call_teddy_repair(request, schema, &query.sql, &error).await {
+                    Ok(repaired) => repaired,
+                    Err(e) => {
+                        tracing::warn!("Repair of query {} failed: {}", query_index, e);
+                        if let Some(last) = debug_trace.last_mut() {
+                            last.repair_error = Some(e.to_string());
+                        }
+                        break;
+                    }
+                };
+                match repaired.queries.into_iter().next() {
+                    Some(repaired) => query.sql = self.correct_query(sanitize(&repaired.sql), dictionary, &mut literal_corrections),
+                    None => break,
+                }
+            }
+            queries.push(query);
+        }
+
+        Ok(AgentResponse {
+            comment: response.comment,
+            queries,
+            literal_corrections,
+            debug_trace,
+        })
+    }
+
+    /// SQLite's error for `sql`, or `None` if it prepares cleanly.
+    async fn validate_query(&self, guard: &ReadOnlyGuard, sql: &str) -> Result<Option<String>, AppError> {
+        let conn = self.db_loader.get_connection().await?;
+        let guard = guard.clone();
+        let sql = sql.to_string();
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<Option<String>> {
+            Ok(match guard.prepare(conn, &sql) {
+                Ok(_) => None,
+                Err(GuardError::Rejected(reason)) => Some(format!("Query rejected: {}", reason)),
+                Err(GuardError::Sqlite(e)) => Some(e.to_string()),
+            })
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
     }
 
     async fn call_dolores(&self, messages: &[String]) -> Result<DoloresResponse, AppError> {
@@ -128,11 +264,32 @@ impl LlmAgent {
     }
 
     async fn call_teddy(&self, filtered_request: &str, schema: &str) -> Result<AgentResponse, AppError> {
-        println!("===============================");
-        println!("Sending request to OpenAI [TEDDY]...");
-        println!("Schema being sent to Teddy:");
-        println!("{}", schema);
-    
+        // The schema holds sample rows and column values, so only its size
+        // is logged
+        tracing::debug!("Sending request to OpenAI [TEDDY] with a {}-byte schema", schema.len());
+
+        self.complete_teddy(schema, filtered_request.to_string()).await
+    }
+
+    async fn call_teddy_repair(
+        &self,
+        filtered_request: &str,
+        schema: &str,
+        sql: &str,
+        error: &str,
+    ) -> Result<AgentResponse, AppError> {
+        tracing::debug!("Sending repair request to OpenAI [TEDDY]...");
+
+        let content = format!(
+            "{}\n\nThis SQL query you generated for the request above failed in SQLite:\n{}\n\nSQLite error: {}\n\n\
+             Fix the query using only the tables and columns in the schema. Return the corrected query \
+             as the only entry in \"queries\", in the same JSON format.",
+            filtered_request, sql, error
+        );
+        self.complete_teddy(schema, content).await
+    }
+
+    async fn complete_teddy(&self, schema: &str, user_content: String) -> Result<AgentResponse, AppError> {
         let messages = vec![
             ChatCompletionRequestMessage::System(
                 ChatCompletionRequestSystemMessage {
@@ -143,7 +300,7 @@ impl LlmAgent {
             ),
             ChatCompletionRequestMessage::User(
                 ChatCompletionRequestUserMessage {
-                    content: ChatCompletionRequestUserMessageContent::Text(filtered_request.to_string()),
+                    content: ChatCompletionRequestUserMessageContent::Text(user_content),
                     name: None,
                     role: Role::User,
                 }
@@ -329,12 +486,15 @@ impl LlmAgent {
             -         - YOU MUST ALWAYS return a JSON object with the following structure:
           {{
             "comment": "A description of what the query does",
-            "queries": ["SQL query string 1", "SQL query string 2", ...]
+            "queries": [
+              {{ "title": "Short title of query 1", "explanation": "What query 1 returns", "sql": "SQL query string 1" }},
+              ...
+            ]
           }}
             - IT IS CRUCIAL that you generate an accurate query using the database schema provided.
 
             **DATABASE SCHEMA AND SAMPLE DATA**:
-            The queries you generate will run on a SQL Lite database with the following schema and the samples rows of each table. The sample rows are the first few rows, and they are provided as an EXAMPLE of the data type, but the COLUMNS are what you MUST focus on for your analysis:
+            The queries you generate will run on a SQL Lite database with the following schema and sample rows of each table. For each column you get its SQL type, the percentage of NULL values, the number of distinct values and, for text columns, either the most frequent values or ALL of its values. The sample rows are a few representative rows, and they are provided as an EXAMPLE of the data type, but the COLUMNS are what you MUST focus on for your analysis. When filtering text columns, use the values EXACTLY as they appear in the listed values and sample rows (for example, if a column lists 'SP' and the user asks for "São Paulo", use 'SP'):
             # START OF SCHEMA WITH SAMPLES #
             {}
             # END SCHEMA WITH SAMPLES  #
@@ -350,6 +510,7 @@ impl LlmAgent {
             
             **HANDLING SCENARIOS**:
             - YOU MUST ALWAYS use double quotes around column and table names in SQL Lite queries to ensure compatibility with special characters, numbers, or spaces.
+            - Each column in the schema lists the ORIGINAL HEADER from the spreadsheet. Users refer to columns by those headers (for example, "Preço Unitário (R$)"), so match their wording against the original headers, but ALWAYS use the SQL column name in the queries.
             - YOU MUST evaluate whether the user request makes sense based on the COLUMNS of the tables provided. The first row is an example of the data type and values, but the COLUMNS determine if the request is valid.
             - If the user request doesn't align with the columns in any way, YOU MUST return a comment stating that there is no such information in the sheets provided.
               - In this case, you must return the following structure with a comment and an EMPTY query array:
@@ -414,7 +575,10 @@ impl LlmAgent {
               
               **OPTIMIZATIONS AND COMPLETENESS OF INFORMATION**:
                 - Your goal is to return the most optimized SQL Lite query that retrieves the necessary information with maximum accuracy. Always prefer a solution that reduces redundant data, but NEVER compromise on the amount of information returned. More is always better, but if the same information can be presented more efficiently with less data, it's an even better result.
-                - When performing queries that involve string pattern matching, if no results are found, make sure to try the ILIKE operator instead of LIKE to ensure the query is case-insensitive. However, you must prioritize the LIKE operator and only use ILIKE if no results are found after the first try.
+                - When performing queries that involve string pattern matching, use LIKE, which ignores case for unaccented letters only. SQLite has NO ILIKE operator. To match regardless of case AND accents, wrap the column in UNACCENT and write the pattern in lowercase without accents (e.g. WHERE UNACCENT("cidade") LIKE '%sao paulo%').
+                - When the schema lists a full-text index for a table, use it to search for words or phrases in its long text columns instead of LIKE '%...%': join the index on rowid and filter with MATCH (e.g. WHERE "contracts_fts" MATCH 'rescisao'). MATCH ignores case and accents; put phrases in double quotes inside the string ('"aviso previo"') and use a trailing * for prefixes ('rescis*').
+                - When a column is marked "references other_table.column", join the two tables on those columns to combine their data (e.g. order totals per customer name).
+                - When the schema lists a view over several sheets, query the view (filtering or grouping by _source_sheet) instead of combining the sheet tables with UNION yourself.
                 - When you are about to generate multiple SELECT statements, think about combining them into a single JOIN query, if possible.
 
                 Example of two SELECT statements:
@@ -442,12 +606,12 @@ impl LlmAgent {
                   SELECT "col2" FROM "table"
                   ORDER BY "col1" DESC;
 
-              - When dealing with the statistical calculation of variance, in SQLite there is no built-in VARIANCE function. To calculate variance, you can manually compute it using the formula for variance. Here is an example of how to do it:
-                - Example of CORRECT variance calculation:
-                  SELECT 
-                    AVG("column_name") AS "Mean", 
-                    AVG(("column_name" - (SELECT AVG("column_name") FROM "table_name")) * ("column_name" - (SELECT AVG("column_name") FROM "table_name"))) AS "Variance"
-                  FROM "table_name";
+              - Besides the built-in SQLite functions, these functions are available:
+                - Statistics (aggregates): STDDEV(x) and VARIANCE(x) (sample), MEDIAN(x), PERCENTILE(x, p) with p from 0 to 100.
+                  - Example: SELECT AVG("price") AS "Mean", STDDEV("price") AS "Std Dev", MEDIAN("price") AS "Median", PERCENTILE("price", 90) AS "P90" FROM "table_name";
+                - Regular expressions: "column" REGEXP 'pattern' (use (?i) in the pattern for case-insensitive matching) and REGEXP_REPLACE("column", 'pattern', 'replacement').
+                - Text: UNACCENT(text) returns the text in lowercase without accents ('São Paulo' → 'sao paulo').
+                - Dates: TO_DATE(text[, format]) turns text such as '15/03/2024' or '15 de março de 2024' into an ISO date ('2024-03-15'); the optional format uses strftime codes (e.g. '%d/%m/%Y', '%d de %B de %Y'). MONTH_NAME(date) returns the month name in Portuguese ('março'), WEEK(date) the ISO week number and QUARTER(date) the quarter (1-4).
 
               - When receiving a request to create a new column or perform an operation, make sure to use the correct SQLite operations.
                 Examples of requests:
@@ -457,7 +621,7 @@ impl LlmAgent {
                   - "Please replace all instances of 'N/A' in the 'Status' column with 'Unknown'"
 
                 In those cases, you can use the operations:
-                  - CONCAT(first_name, ' ', last_name)
+                  - first_name || ' ' || last_name (SQLite has no CONCAT function; always use ||)
                   - SUBSTR(email, INSTR(email, '@') + 1)
                   - CASE WHEN discount > 0 THEN 'Yes' ELSE 'No' END
                   - REPLACE(status, 'N/A', 'Unknown')
@@ -473,8 +637,12 @@ impl LlmAgent {
                 {{
                 "comment": "Description of what the queries do",
                 "queries": [
-                    "SELECT ... FROM ...",
-                    "Another SQL query if needed"
+                    {{
+                    "title": "Short title for the result, in the user's language",
+                    "explanation": "One sentence on what this query returns and how",
+                    "sql": "SELECT ... FROM ..."
+                    }},
+                    {{ "title": "...", "explanation": "...", "sql": "Another SQL query if needed" }}
                 ]
                 }}
                 
@@ -488,8 +656,8 @@ impl LlmAgent {
     }
 
     fn parse_dolores_response(&self, response: &str) -> Result<DoloresResponse, AppError> {
-        println!("Raw Dolores response: {}", response);
-        
+        tracing::debug!("Received a {}-byte Dolores response", response.len());
+
         let re = Regex::new(r"\{[\s\S]*\}").map_err(|e| {
             AppError::ParseError(format!("Failed to create regex: {}", e))
         })?;
@@ -512,8 +680,8 @@ impl LlmAgent {
     }
 
     fn parse_teddy_response(&self, response: &str) -> Result<AgentResponse, AppError> {
-        println!("Raw Teddy response: {}", response);
-        
+        tracing::debug!("Received a {}-byte Teddy response", response.len());
+
         let re = Regex::new(r"\{[\s\S]*\}").map_err(|e| {
             AppError::ParseError(format!("Failed to create regex: {}", e))
         })?;
@@ -532,99 +700,110 @@ impl LlmAgent {
             .ok_or_else(|| AppError::ParseError("Missing or invalid 'comment' field".to_string()))?
             .to_string();
         
+        // Queries are objects with title/explanation/sql; bare strings are still accepted
         let queries = v["queries"].as_array()
             .ok_or_else(|| AppError::ParseError("Missing or invalid 'queries' field".to_string()))?
             .iter()
-            .filter_map(|v| v.as_str())
-            .map(String::from)
+            .filter_map(|v| match v {
+                Value::String(sql) => Some(GeneratedQuery {
+                    sql: sql.clone(),
+                    title: String::new(),
+                    explanation: String::new(),
+                }),
+                Value::Object(_) => serde_json::from_value(v.clone()).ok(),
+                _ => None,
+            })
             .collect();
         
-        Ok(AgentResponse { comment, queries })
+        Ok(AgentResponse { comment, queries, literal_corrections: Vec::new(), debug_trace: Vec::new() })
+    }
+
+    /// Replaces WHERE/IN literals that don't exist in the data with their closest real value.
+    fn correct_literals(&self, response: AgentResponse, dictionary: &ValueDictionary) -> AgentResponse {
+        let mut literal_corrections = response.literal_corrections;
+        let queries = response.queries
+            .into_iter()
+            .map(|query| GeneratedQuery {
+                sql: self.correct_query(query.sql, dictionary, &mut literal_corrections),
+                ..query
+            })
+            .collect();
+
+        AgentResponse {
+            comment: response.comment,
+            queries,
+            literal_corrections,
+            debug_trace: response.debug_trace,
+        }
+    }
+
+    fn correct_query(&self, query: String, dictionary: &ValueDictionary, literal_corrections: &mut Vec<LiteralCorrection>) -> String {
+        if dictionary.is_empty() {
+            return query;
+        }
+
+        let (corrected, corrections) = dictionary.correct_literals(&query);
+        for correction in &corrections {
+            tracing::info!(
+                "Corrected literal '{}' to '{}' for column {}",
+                correction.original, correction.corrected, correction.column
+            );
+        }
+        literal_corrections.extend(corrections);
+        corrected
     }
 
     fn sanitize_values(&self, response: AgentResponse) -> AgentResponse {
         AgentResponse {
-            comment: response.comment
-                .replace('\u{0}', "")
-                .replace('\u{1F}', ""),
+            comment: sanitize(&response.comment),
             queries: response.queries
-                .into_iter()
-                .map(|q| q.replace('\u{0}', "").replace('\u{1F}', ""))
+                .iter()
+                .map(|q| GeneratedQuery {
+                    sql: sanitize(&q.sql),
+                    title: sanitize(&q.title),
+                    explanation: sanitize(&q.explanation),
+                })
                 .collect(),
+            literal_corrections: response.literal_corrections,
+            debug_trace: response.debug_trace,
         }
     }
 
+    /// Runs the queries under the configured deadline and row cap. Each
+    /// query gets a result id so its full result can be paged later.
     pub async fn execute_queries(&self, response: AgentResponse) -> Result<QueryResult, AppError> {
-        let conn = self.db_loader.get_connection().await?;
-        let mut json_results = Vec::new();
-        
-        if response.queries.is_empty() {
-            return Ok(QueryResult {
-                comment: response.comment,
-                data: json_results,
-            });
-        }
-
-        for sql_query in response.queries {
-            tracing::info!("Executing SQL query: {}", sql_query);
-            
-            let results = conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<serde_json::Value> {
-                let mut stmt = conn.prepare(&sql_query)?;
-                
-                let column_names: Vec<String> = stmt
-                    .column_names()
-                    .into_iter()
-                    .map(String::from)
-                    .collect();
-                
-                let column_count = stmt.column_count();
-                let mut rows_data = Vec::new();
-                
-                let mut rows = stmt.query([])?;
-
-                while let Some(row) = rows.next()? {
-                    let mut row_values = Vec::new();
-                    for i in 0..column_count {
-                        let value = match row.get_ref(i)? {
-                            ValueRef::Null => SqlValue::Null,
-                            ValueRef::Integer(i) => SqlValue::Integer(i),
-                            ValueRef::Real(f) => SqlValue::Float(f),
-                            ValueRef::Text(t) => SqlValue::Text(String::from_utf8_lossy(t).into_owned()),
-                            ValueRef::Blob(_) => SqlValue::Blob,
-                        };
-                        row_values.push(value);
-                    }
-                    rows_data.push(row_values);
+        let sql = response.queries.iter().map(|query| query.sql.clone()).collect();
+        let row_sets = self.runner.run(sql, self.runner.max_rows()).await?;
+
+        let results = response.queries
+            .into_iter()
+            .zip(row_sets)
+            .map(|(query, rows)| {
+                if let Some(error) = &rows.error {
+                    tracing::warn!("Query failed: {} ({})", query.sql, error);
+                } else if rows.truncated {
+                    tracing::info!("Query returned {} of {:?} rows", rows.row_count, rows.total_row_count);
+                }
+                ExecutedQuery {
+                    result_id: uuid::Uuid::new_v4().to_string(),
+                    sql: query.sql,
+                    title: query.title,
+                    explanation: query.explanation,
+                    rows,
                 }
-
-                Ok(serde_json::json!({
-                    "columns": column_names,
-                    "rows": rows_data.iter().map(|row| {
-                        row.iter().map(|value| match value {
-                            SqlValue::Null => JsonValue::Null,
-                            SqlValue::Integer(i) => JsonValue::Number((*i).into()),
-                            SqlValue::Float(f) => {
-                                if f.is_finite() {
-                                    JsonValue::Number(serde_json::Number::from_f64(*f).unwrap_or(0.into()))
-                                } else {
-                                    JsonValue::Null
-                                }
-                            },
-                            SqlValue::Text(s) => JsonValue::String(s.clone()),
-                            SqlValue::Blob => JsonValue::String("BLOB".to_string()),
-                        }).collect::<Vec<_>>()
-                    }).collect::<Vec<_>>()
-                }))
             })
-            .await
-            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
-
-            json_results.push(results);
-        }
+            .collect();
 
         Ok(QueryResult {
             comment: response.comment,
-            data: json_results,
+            results,
+            literal_corrections: response.literal_corrections,
+            debug_trace: response.debug_trace,
         })
     }
 }
+
+/// Strips control characters the model sometimes emits.
+fn sanitize(text: &str) -> String {
+    text.replace(['\u{0}', '\u{1F}'], "")
+}
@@ -1,4 +1,12 @@
+pub mod cpu_pool;
 pub mod file_processor;
 pub mod db_loader;
 pub mod llm_agent;
-pub mod excel;
\ No newline at end of file
+pub mod query_runner;
+pub mod retention;
+pub mod session;
+pub mod excel;
+pub mod schema;
+pub mod sql_functions;
+pub mod sql_guard;
+pub mod value_dictionary;
\ No newline at end of file
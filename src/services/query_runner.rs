@@ -0,0 +1,431 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rusqlite::types::ValueRef;
+use rusqlite::ErrorCode;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use crate::error::AppError;
+use crate::services::db_loader::DbLoader;
+use crate::services::sql_guard::{GuardError, ReadOnlyGuard};
+
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MAX_RESULT_ROWS: usize = 1000;
+/// SQLite VM instructions between deadline/cancellation checks.
+const PROGRESS_CHECK_INTERVAL: i32 = 10_000;
+/// How long a query may keep running past the row cap just to count the
+/// rows it would have returned.
+const COUNT_TIME_BUDGET: Duration = Duration::from_millis(500);
+/// Rows of one result kept for paging; later rows can't be paged to.
+pub const MAX_STORED_RESULT_ROWS: usize = 100_000;
+
+/// Rows produced by one read-only query. A failed query carries its error
+/// and no rows.
+#[derive(Debug, Serialize)]
+pub struct RowSet {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Vec<JsonValue>>,
+    /// Rows returned in `rows`.
+    pub row_count: usize,
+    /// Rows the query produced, counted past the row cap. `None` when the
+    /// deadline ran out before counting finished.
+    pub total_row_count: Option<usize>,
+    /// Set when `rows` holds only the first `row_count` of the results.
+    pub truncated: bool,
+    pub execution_ms: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultColumn {
+    pub name: String,
+    /// Declared type of the source column, when the result column maps to one.
+    pub declared_type: Option<String>,
+    /// SQLite storage class of the first non-null value (INTEGER, REAL, TEXT or BLOB).
+    pub sqlite_type: Option<String>,
+}
+
+/// A result column to sort a stored result by.
+#[derive(Debug, Clone, Copy)]
+pub struct SortKey {
+    /// Position of the column in the result.
+    pub column: usize,
+    pub descending: bool,
+}
+
+/// One page of a stored result.
+#[derive(Debug)]
+pub struct StoredPage {
+    pub rows: Vec<Vec<JsonValue>>,
+    /// Rows kept for paging: the whole result, up to `MAX_STORED_RESULT_ROWS`.
+    pub stored_row_count: usize,
+    pub execution_ms: f64,
+}
+
+/// Runs generated SQL against a session database under the read-only guard,
+/// a per-query deadline and a row cap.
+#[derive(Clone)]
+pub struct QueryRunner {
+    db_loader: DbLoader,
+    query_timeout: Duration,
+    max_rows: usize,
+}
+
+impl QueryRunner {
+    pub fn new(db_loader: DbLoader) -> Self {
+        Self {
+            db_loader,
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+            max_rows: DEFAULT_MAX_RESULT_ROWS,
+        }
+    }
+
+    /// Sets the per-query execution deadline and the number of rows returned
+    /// per query.
+    pub fn with_limits(mut self, query_timeout: Duration, max_rows: usize) -> Self {
+        self.query_timeout = query_timeout;
+        self.max_rows = max_rows;
+        self
+    }
+
+    pub fn max_rows(&self) -> usize {
+        self.max_rows
+    }
+
+    /// Generated SQL may only read the loaded tables, their full-text
+    /// indexes and the catalog.
+    pub async fn read_only_guard(&self) -> Result<ReadOnlyGuard, AppError> {
+        Ok(ReadOnlyGuard::new(self.db_loader.get_queryable_tables().await?))
+    }
+
+    /// Runs the queries one at a time, returning at most `max_rows` rows from
+    /// each. If this future is dropped (e.g. the client disconnected), the
+    /// running statement is interrupted and the rest are skipped.
+    pub async fn run(&self, queries: Vec<String>, max_rows: usize) -> Result<Vec<RowSet>, AppError> {
+        let guard = self.read_only_guard().await?;
+        let conn = self.db_loader.get_connection().await?;
+        let cancel = CancelOnDrop::default();
+        let mut results = Vec::with_capacity(queries.len());
+
+        for sql in queries {
+            tracing::info!("Executing SQL query: {}", sql);
+
+            let limits = QueryLimits {
+                guard: guard.clone(),
+                deadline: Instant::now() + self.query_timeout,
+                max_rows: max_rows.min(self.max_rows),
+                cancelled: cancel.flag(),
+            };
+            let rows = conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<RowSet> {
+                conn.progress_handler(PROGRESS_CHECK_INTERVAL, Some(limits.interrupt_check(limits.deadline)));
+                let rows = execute_query(conn, &limits, &sql);
+                conn.progress_handler(0, None::<fn() -> bool>);
+                Ok(rows)
+            })
+            .await
+            .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+            results.push(rows);
+        }
+
+        Ok(results)
+    }
+
+    /// Returns up to `limit` rows of `sql`'s result after skipping `offset`.
+    /// The first request for a result saves its rows, in the order the query
+    /// returned them, to the temporary table `table`; pages are then read
+    /// from that table, so they don't shift between requests. Unsorted pages
+    /// seek by rowid, which is the row's position in the result. Sorted
+    /// pages break ties by that position.
+    pub async fn page(
+        &self,
+        table: &str,
+        sql: &str,
+        sort: &[SortKey],
+        offset: usize,
+        limit: usize,
+    ) -> Result<StoredPage, AppError> {
+        let guard = self.read_only_guard().await?;
+        let conn = self.db_loader.get_connection().await?;
+        let cancel = CancelOnDrop::default();
+        let limits = QueryLimits {
+            guard,
+            deadline: Instant::now() + self.query_timeout,
+            max_rows: MAX_STORED_RESULT_ROWS,
+            cancelled: cancel.flag(),
+        };
+        let table = table.to_string();
+        let sql = sql.to_string();
+        let sort = sort.to_vec();
+        let limit = limit.min(self.max_rows);
+
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<Result<StoredPage, AppError>> {
+            let start = Instant::now();
+            conn.progress_handler(PROGRESS_CHECK_INTERVAL, Some(limits.interrupt_check(limits.deadline)));
+            let page = read_stored_page(conn, &limits, &table, &sql, &sort, offset, limit);
+            conn.progress_handler(0, None::<fn() -> bool>);
+            Ok(page.map(|mut page| {
+                page.execution_ms = start.elapsed().as_secs_f64() * 1000.0;
+                page
+            }))
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?
+    }
+}
+
+/// Sets the shared flag when dropped, so statements still running on the
+/// database thread are interrupted once nobody is waiting for them.
+#[derive(Default)]
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl CancelOnDrop {
+    fn flag(&self) -> Arc<AtomicBool> {
+        self.0.clone()
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+struct QueryLimits {
+    guard: ReadOnlyGuard,
+    deadline: Instant,
+    max_rows: usize,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl QueryLimits {
+    /// Progress handler that interrupts the statement once `deadline`
+    /// passes or the request is cancelled.
+    fn interrupt_check(&self, deadline: Instant) -> impl FnMut() -> bool + Send + std::panic::RefUnwindSafe + 'static {
+        let cancelled = self.cancelled.clone();
+        move || cancelled.load(Ordering::Relaxed) || Instant::now() >= deadline
+    }
+
+    fn interruption_reason(&self) -> String {
+        if self.cancelled.load(Ordering::Relaxed) {
+            "Query cancelled".to_string()
+        } else {
+            "Query exceeded the execution time limit".to_string()
+        }
+    }
+}
+
+/// Runs one query, capturing any failure in the result.
+fn execute_query(conn: &rusqlite::Connection, limits: &QueryLimits, sql: &str) -> RowSet {
+    let start = Instant::now();
+    let mut result = RowSet {
+        columns: Vec::new(),
+        rows: Vec::new(),
+        row_count: 0,
+        total_row_count: None,
+        truncated: false,
+        execution_ms: 0.0,
+        error: None,
+    };
+
+    let outcome = match limits.guard.prepare(conn, sql) {
+        Ok(mut stmt) => {
+            result.columns = stmt.columns()
+                .into_iter()
+                .map(|column| ResultColumn {
+                    name: column.name().to_string(),
+                    declared_type: column.decl_type().map(str::to_string),
+                    sqlite_type: None,
+                })
+                .collect();
+            read_rows(conn, &mut stmt, limits, &mut result).map_err(|e| match e.sqlite_error_code() {
+                Some(ErrorCode::OperationInterrupted) => limits.interruption_reason(),
+                _ => e.to_string(),
+            })
+        }
+        Err(GuardError::Rejected(reason)) => Err(format!("Query rejected: {}", reason)),
+        Err(GuardError::Sqlite(e)) => Err(e.to_string()),
+    };
+
+    if let Err(error) = outcome {
+        result.rows.clear();
+        result.total_row_count = None;
+        result.truncated = false;
+        result.error = Some(error);
+    }
+    result.row_count = result.rows.len();
+    result.execution_ms = start.elapsed().as_secs_f64() * 1000.0;
+    result
+}
+
+/// Saves the result to `table` if that wasn't done yet, then reads the page.
+fn read_stored_page(
+    conn: &mut rusqlite::Connection,
+    limits: &QueryLimits,
+    table: &str,
+    sql: &str,
+    sort: &[SortKey],
+    offset: usize,
+    limit: usize,
+) -> Result<StoredPage, AppError> {
+    let sqlite_error = |e: rusqlite::Error| match e.sqlite_error_code() {
+        Some(ErrorCode::OperationInterrupted) => AppError::DatabaseError(limits.interruption_reason()),
+        _ => AppError::DatabaseError(e.to_string()),
+    };
+    let quoted_table = format!("temp.\"{}\"", table.replace('"', "\"\""));
+
+    let exists = conn.query_row(
+        "SELECT count(*) > 0 FROM sqlite_temp_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get::<_, bool>(0),
+    )
+    .map_err(sqlite_error)?;
+    // Rows get rowids 1..=n in the order they were stored
+    let stored_row_count = if exists {
+        let max_rowid: i64 = conn.query_row(&format!("SELECT coalesce(max(rowid), 0) FROM {}", quoted_table), [], |row| row.get(0))
+            .map_err(sqlite_error)?;
+        max_rowid as usize
+    } else {
+        store_rows(conn, limits, &quoted_table, sql).map_err(sqlite_error)??
+    };
+
+    if offset > stored_row_count {
+        return Err(AppError::InvalidInput(format!(
+            "offset {} is past the end of the result ({} rows)",
+            offset, stored_row_count
+        )));
+    }
+    let page_sql = if sort.is_empty() {
+        format!("SELECT * FROM {} WHERE rowid > ?1 ORDER BY rowid LIMIT ?2", quoted_table)
+    } else {
+        let order_by: Vec<String> = sort.iter()
+            .map(|key| format!("c{} {}", key.column, if key.descending { "DESC" } else { "ASC" }))
+            .collect();
+        format!("SELECT * FROM {} ORDER BY {}, rowid LIMIT ?2 OFFSET ?1", quoted_table, order_by.join(", "))
+    };
+
+    // Both are at most MAX_STORED_RESULT_ROWS, so they fit in an i64
+    let mut stmt = conn.prepare(&page_sql).map_err(sqlite_error)?;
+    let column_count = stmt.column_count();
+    let rows = stmt.query_map([offset as i64, limit as i64], |row| {
+        (0..column_count).map(|i| row.get_ref(i).map(value_to_json)).collect::<rusqlite::Result<Vec<_>>>()
+    })
+    .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+    .map_err(sqlite_error)?;
+
+    Ok(StoredPage {
+        rows,
+        stored_row_count,
+        execution_ms: 0.0,
+    })
+}
+
+/// Runs `sql` under the guard and copies its first `MAX_STORED_RESULT_ROWS`
+/// rows into `table`, with columns `c0`, `c1`, ... in result order. Nothing
+/// is kept if the query fails or is interrupted.
+fn store_rows(
+    conn: &mut rusqlite::Connection,
+    limits: &QueryLimits,
+    table: &str,
+    sql: &str,
+) -> rusqlite::Result<Result<usize, AppError>> {
+    let tx = conn.transaction()?;
+    let stored = {
+        let mut stmt = match limits.guard.prepare(&tx, sql) {
+            Ok(stmt) => stmt,
+            Err(GuardError::Rejected(reason)) => {
+                return Ok(Err(AppError::DatabaseError(format!("Query rejected: {}", reason))));
+            }
+            Err(GuardError::Sqlite(e)) => return Err(e),
+        };
+        let column_count = stmt.column_count();
+        let columns: Vec<String> = (0..column_count).map(|i| format!("c{}", i)).collect();
+        let placeholders: Vec<String> = (1..=column_count).map(|i| format!("?{}", i)).collect();
+        tx.execute(&format!("CREATE TEMP TABLE {} ({})", table, columns.join(", ")), [])?;
+        let mut insert = tx.prepare(&format!("INSERT INTO {} VALUES ({})", table, placeholders.join(", ")))?;
+
+        let mut rows = stmt.query([])?;
+        let mut stored = 0;
+        while stored < limits.max_rows {
+            let Some(row) = rows.next()? else { break };
+            let values = (0..column_count)
+                .map(|i| row.get::<_, rusqlite::types::Value>(i))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            insert.execute(rusqlite::params_from_iter(values))?;
+            stored += 1;
+        }
+        stored
+    };
+    tx.commit()?;
+    Ok(Ok(stored))
+}
+
+/// Collects up to `max_rows` rows, then keeps stepping only to count the
+/// rest, for at most `COUNT_TIME_BUDGET`. If that runs out while counting,
+/// the collected rows are kept and the total is left unknown.
+fn read_rows(
+    conn: &rusqlite::Connection,
+    stmt: &mut rusqlite::Statement<'_>,
+    limits: &QueryLimits,
+    result: &mut RowSet,
+) -> rusqlite::Result<()> {
+    let column_count = result.columns.len();
+    let mut rows = stmt.query([])?;
+    while result.rows.len() < limits.max_rows {
+        let Some(row) = rows.next()? else {
+            result.total_row_count = Some(result.rows.len());
+            return Ok(());
+        };
+        let mut values = Vec::with_capacity(column_count);
+        for (i, column) in result.columns.iter_mut().enumerate() {
+            let value = row.get_ref(i)?;
+            if column.sqlite_type.is_none() {
+                column.sqlite_type = storage_class(value).map(str::to_string);
+            }
+            values.push(value_to_json(value));
+        }
+        result.rows.push(values);
+    }
+
+    // Counting holds the connection, so it gets a shorter deadline than the query
+    let count_deadline = limits.deadline.min(Instant::now() + COUNT_TIME_BUDGET);
+    conn.progress_handler(PROGRESS_CHECK_INTERVAL, Some(limits.interrupt_check(count_deadline)));
+
+    let mut total = result.rows.len();
+    loop {
+        match rows.next() {
+            Ok(Some(_)) => total += 1,
+            Ok(None) => {
+                result.truncated = total > result.rows.len();
+                result.total_row_count = Some(total);
+                return Ok(());
+            }
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::OperationInterrupted)
+                && !limits.cancelled.load(Ordering::Relaxed) =>
+            {
+                result.truncated = true;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn storage_class(value: ValueRef<'_>) -> Option<&'static str> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(_) => Some("INTEGER"),
+        ValueRef::Real(_) => Some("REAL"),
+        ValueRef::Text(_) => Some("TEXT"),
+        ValueRef::Blob(_) => Some("BLOB"),
+    }
+}
+
+fn value_to_json(value: ValueRef<'_>) -> JsonValue {
+    match value {
+        ValueRef::Null => JsonValue::Null,
+        ValueRef::Integer(i) => JsonValue::Number(i.into()),
+        ValueRef::Real(f) => serde_json::Number::from_f64(f).map_or(JsonValue::Null, JsonValue::Number),
+        ValueRef::Text(t) => JsonValue::String(String::from_utf8_lossy(t).into_owned()),
+        ValueRef::Blob(_) => JsonValue::String("BLOB".to_string()),
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::services::file_processor;
+use crate::services::session::{Session, SessionStore};
+
+/// How long each class of user data is kept.
+#[derive(Debug, Clone, Copy)]
+pub struct RetentionPolicy {
+    /// Downloaded files, parsed workbooks and their analyses.
+    pub files: Duration,
+    /// Session databases, counted from when the session was created.
+    pub sessions: Duration,
+    /// Stored query results of sessions that are kept.
+    pub results: Duration,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            files: Duration::from_secs(config.retention_files_secs),
+            sessions: Duration::from_secs(config.retention_sessions_secs),
+            results: Duration::from_secs(config.retention_results_secs),
+        }
+    }
+}
+
+/// A file downloaded on a user's behalf. The file caches are keyed by content
+/// hash alone, so this is what ties cached contents to a user.
+#[derive(PartialEq, Eq, Hash)]
+struct FileRecord {
+    user_email: String,
+    hash: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedSession {
+    pub session_id: String,
+    pub files: Vec<String>,
+    pub tables: usize,
+    pub query_results: usize,
+}
+
+/// What a purge or an erasure deleted.
+#[derive(Debug, Default, Serialize)]
+pub struct DeletionReport {
+    /// Sessions removed with their databases.
+    pub sessions: Vec<DeletedSession>,
+    /// Downloaded files dropped from the caches, with their parsed workbooks
+    /// and analyses.
+    pub cached_files: usize,
+    /// Cached files left in place because another user also uses them.
+    pub shared_cached_files: usize,
+    /// Stored query results dropped from sessions that were kept.
+    pub query_results: usize,
+    /// Database files in the session directory that no session used.
+    pub orphaned_session_files: usize,
+    /// Deletions that failed, with their errors. The rest still ran.
+    pub failures: Vec<String>,
+}
+
+impl DeletionReport {
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+            && self.cached_files == 0
+            && self.query_results == 0
+            && self.orphaned_session_files == 0
+            && self.failures.is_empty()
+    }
+
+    async fn delete_sessions(&mut self, sessions: Vec<Arc<Session>>) {
+        for session in sessions {
+            match delete_session(&session).await {
+                Ok(deleted) => self.sessions.push(deleted),
+                Err(e) => self.failures.push(format!("Session {}: {}", session.id, e)),
+            }
+        }
+    }
+
+    async fn forget_file(&mut self, hash: &str) {
+        match file_processor::forget_file(hash).await {
+            Ok(true) => self.cached_files += 1,
+            Ok(false) => {}
+            Err(e) => self.failures.push(format!("Cached file {}: {}", hash, e)),
+        }
+    }
+}
+
+/// Enforces the retention policy and erases a user's data on request.
+#[derive(Clone)]
+pub struct DataRetention {
+    policy: RetentionPolicy,
+    sessions: SessionStore,
+    /// When each user last used each file.
+    files: Arc<Mutex<HashMap<FileRecord, Instant>>>,
+}
+
+impl DataRetention {
+    pub fn new(policy: RetentionPolicy, sessions: SessionStore) -> Self {
+        Self {
+            policy,
+            sessions,
+            files: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Notes that the file with this content hash was used for `user_email`,
+    /// restarting its retention period.
+    pub fn record_file(&self, user_email: &str, hash: &str) {
+        let Ok(mut files) = self.files.lock() else { return };
+        let record = FileRecord {
+            user_email: user_email.to_string(),
+            hash: hash.to_string(),
+        };
+        files.insert(record, Instant::now());
+    }
+
+    /// Deletes everything older than the policy allows. A deletion that
+    /// fails is recorded in the report and doesn't stop the others.
+    pub async fn purge_expired(&self) -> DeletionReport {
+        let mut report = DeletionReport::default();
+
+        let max_age = self.policy.sessions;
+        report.delete_sessions(self.sessions.remove_where(|session| session.created_at.elapsed() > max_age)).await;
+        for session in self.sessions.all() {
+            report.query_results += session.purge_results(Some(self.policy.results)).await;
+        }
+        report.orphaned_session_files = self.sessions.remove_orphaned_files().await;
+
+        // A file downloaded again since is kept until that copy expires
+        let expired = self.take_files(|_, stored_at| stored_at.elapsed() > self.policy.files);
+        let kept = self.recorded_hashes();
+        for hash in expired.difference(&kept) {
+            report.forget_file(hash).await;
+        }
+
+        report
+    }
+
+    /// Deletes the sessions owned by `user_email`, their stored results and
+    /// the files cached for them. Other users' sessions are left alone, and
+    /// so is a cached file another user also downloaded or still has loaded.
+    /// Like `purge_expired`, it carries on past failed deletions.
+    pub async fn erase_user(&self, user_email: &str) -> DeletionReport {
+        let mut report = DeletionReport::default();
+
+        report.delete_sessions(self.sessions.remove_where(|session| session.user_email == user_email)).await;
+
+        let hashes = self.take_files(|record, _| record.user_email == user_email);
+        let mut shared = self.recorded_hashes();
+        shared.extend(self.sessions.all().iter().flat_map(|session| session.file_hashes()));
+        for hash in &hashes {
+            if shared.contains(hash) {
+                report.shared_cached_files += 1;
+            } else {
+                report.forget_file(hash).await;
+            }
+        }
+
+        tracing::info!(
+            "Erased data for a user: {} sessions, {} cached files ({} kept for other users), {} failures",
+            report.sessions.len(),
+            report.cached_files,
+            report.shared_cached_files,
+            report.failures.len()
+        );
+        report
+    }
+
+    /// Runs `purge_expired` every `interval` for the life of the process.
+    pub fn spawn_purger(self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let report = self.purge_expired().await;
+                for failure in &report.failures {
+                    tracing::warn!("Retention purge could not delete: {}", failure);
+                }
+                if !report.is_empty() {
+                    tracing::info!(
+                        "Purged {} sessions, {} cached files and {} query results past retention, \
+                         and {} orphaned session databases",
+                        report.sessions.len(),
+                        report.cached_files,
+                        report.query_results,
+                        report.orphaned_session_files
+                    );
+                }
+            }
+        })
+    }
+
+    /// Removes the matching file records, returning their hashes.
+    fn take_files(&self, matches: impl Fn(&FileRecord, Instant) -> bool) -> HashSet<String> {
+        let Ok(mut files) = self.files.lock() else { return HashSet::new() };
+        let mut taken = HashSet::new();
+        files.retain(|record, stored_at| {
+            if matches(record, *stored_at) {
+                taken.insert(record.hash.clone());
+                false
+            } else {
+                true
+            }
+        });
+        taken
+    }
+
+    fn recorded_hashes(&self) -> HashSet<String> {
+        self.files.lock()
+            .map(|files| files.keys().map(|record| record.hash.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Drops a removed session's tables and results right away, rather than
+/// when the last request holding it finishes.
+async fn delete_session(session: &Session) -> Result<DeletedSession, AppError> {
+    let _loading = session.lock_loading().await;
+    let mut files: Vec<String> = session.db_loader.get_catalog().await?
+        .into_iter()
+        .map(|entry| entry.file_name)
+        .collect();
+    files.sort();
+    files.dedup();
+
+    let mut tables = 0;
+    for file_name in &files {
+        tables += session.db_loader.remove_file(file_name).await?.len();
+    }
+    session.db_loader.clear_cache();
+
+    Ok(DeletedSession {
+        session_id: session.id.clone(),
+        files,
+        tables,
+        query_results: session.purge_results(None).await,
+    })
+}
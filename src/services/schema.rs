@@ -0,0 +1,233 @@
+use std::fmt::Write;
+
+/// Representative rows shown per table at full detail.
+pub const SAMPLE_ROWS: usize = 3;
+/// Most frequent values shown per text column at full detail.
+pub const TOP_VALUES: usize = 5;
+const CHARS_PER_TOKEN: usize = 4;
+const MAX_CELL_CHARS: usize = 40;
+
+#[derive(Debug, Clone)]
+pub struct TableSource {
+    pub file_name: String,
+    pub sheet_name: String,
+    pub cell_range: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnProfile {
+    pub name: String,
+    pub original_header: Option<String>,
+    pub inferred_type: Option<String>,
+    pub sql_type: String,
+    pub null_count: usize,
+    pub distinct_count: usize,
+    pub top_values: Vec<(String, usize)>,
+    /// Complete list of distinct values for low-cardinality text columns.
+    pub values: Vec<String>,
+    /// Set on the table's inferred primary key.
+    pub primary_key: bool,
+    /// Inferred foreign key, as (table, column).
+    pub references: Option<(String, String)>,
+}
+
+/// FTS5 index over some of a table's text columns, joined back on rowid.
+#[derive(Debug, Clone)]
+pub struct FullTextIndex {
+    pub fts_table: String,
+    pub columns: Vec<String>,
+}
+
+/// Sheets stacked by a `UNION ALL` view, in order.
+#[derive(Debug, Clone)]
+pub struct UnionSource {
+    pub sheets: Vec<String>,
+    pub tables: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableProfile {
+    pub name: String,
+    pub source: Option<TableSource>,
+    pub row_count: usize,
+    pub columns: Vec<ColumnProfile>,
+    pub sample_rows: Vec<Vec<String>>,
+    pub full_text: Option<FullTextIndex>,
+    /// Set when this is a view over several sheets.
+    pub union_of: Option<UnionSource>,
+    /// The view this table's rows also appear in.
+    pub part_of: Option<String>,
+}
+
+/// How much of each profile goes into the prompt. We step down through these
+/// until the rendered schema fits the token budget.
+#[derive(Debug, Clone, Copy)]
+enum Detail {
+    Full,
+    Compact,
+    ColumnsOnly,
+}
+
+impl Detail {
+    fn sample_rows(self) -> usize {
+        match self {
+            Detail::Full => SAMPLE_ROWS,
+            Detail::Compact => 1,
+            Detail::ColumnsOnly => 0,
+        }
+    }
+
+    fn top_values(self) -> usize {
+        match self {
+            Detail::Full => TOP_VALUES,
+            Detail::Compact => 3,
+            Detail::ColumnsOnly => 0,
+        }
+    }
+}
+
+const CATALOG_NOTE: &str =
+    "Table _catalog lists every loaded table (table_name, file_name, sheet_name, cell_range, row_count, loaded_at, primary_key).\n";
+
+/// Renders the profiles as prompt text within roughly `token_budget` tokens.
+pub fn render_schema(tables: &[TableProfile], token_budget: usize) -> String {
+    let budget_chars = token_budget * CHARS_PER_TOKEN;
+
+    for detail in [Detail::Full, Detail::Compact] {
+        let schema = tables.iter()
+            .map(|table| render_table(table, detail))
+            .collect::<String>() + CATALOG_NOTE;
+        if schema.len() <= budget_chars {
+            return schema;
+        }
+    }
+
+    // Bare column listings, cut at a table boundary if even those don't fit
+    let mut schema = String::with_capacity(budget_chars);
+    for (idx, table) in tables.iter().enumerate() {
+        let section = render_table(table, Detail::ColumnsOnly);
+        if schema.len() + section.len() + CATALOG_NOTE.len() > budget_chars {
+            let _ = writeln!(
+                schema,
+                "... {} more table(s) omitted; query _catalog to list them.\n",
+                tables.len() - idx
+            );
+            break;
+        }
+        schema.push_str(&section);
+    }
+    schema.push_str(CATALOG_NOTE);
+    schema
+}
+
+fn render_table(table: &TableProfile, detail: Detail) -> String {
+    let mut out = String::with_capacity(512);
+    if let Some(union) = &table.union_of {
+        let sheets: Vec<String> = union.sheets.iter().map(|sheet| format!("\"{}\"", sheet)).collect();
+        let _ = writeln!(out, "View: {} ({} rows)", table.name, table.row_count);
+        let _ = writeln!(
+            out,
+            "All rows of sheets {} (tables {}); column _source_sheet holds each row's sheet. \
+             Use this view for questions spanning these sheets.",
+            sheets.join(", "),
+            union.tables.join(", ")
+        );
+    } else {
+        let _ = writeln!(out, "Table: {} ({} rows)", table.name, table.row_count);
+    }
+    if let Some(view_name) = &table.part_of {
+        let _ = writeln!(out, "Also included in view {}", view_name);
+    }
+    if let Some(source) = &table.source {
+        let _ = writeln!(
+            out,
+            "Source: file \"{}\", sheet \"{}\"{}",
+            source.file_name,
+            source.sheet_name,
+            source.cell_range.as_ref().map(|r| format!(" ({})", r)).unwrap_or_default()
+        );
+    }
+
+    out.push_str("Columns:\n");
+    for column in &table.columns {
+        let _ = write!(out, "  - {} {}", column.name, column.sql_type);
+
+        let mut details = Vec::new();
+        if column.primary_key {
+            details.push("primary key".to_string());
+        }
+        if let Some((to_table, to_column)) = &column.references {
+            details.push(format!("references {}.{}", to_table, to_column));
+        }
+        if let Some(original) = &column.original_header {
+            details.push(format!("original: \"{}\"", original));
+        }
+        if let Some(inferred) = &column.inferred_type {
+            details.push(inferred.clone());
+        }
+        if !matches!(detail, Detail::ColumnsOnly) && table.row_count > 0 {
+            details.push(format!(
+                "{:.0}% null",
+                column.null_count as f64 * 100.0 / table.row_count as f64
+            ));
+            details.push(format!("{} distinct", column.distinct_count));
+        }
+        if matches!(detail, Detail::Full) && !column.values.is_empty() {
+            let values: Vec<String> = column.values.iter()
+                .map(|value| format!("'{}'", truncate(value)))
+                .collect();
+            details.push(format!("all values: {}", values.join(", ")));
+        } else {
+            let top: Vec<String> = column.top_values.iter()
+                .take(detail.top_values())
+                .map(|(value, count)| format!("'{}' ({})", truncate(value), count))
+                .collect();
+            if !top.is_empty() {
+                details.push(format!("top: {}", top.join(", ")));
+            }
+        }
+
+        if !details.is_empty() {
+            let _ = write!(out, " ({})", details.join("; "));
+        }
+        out.push('\n');
+    }
+
+    if let Some(index) = &table.full_text {
+        let _ = writeln!(
+            out,
+            "Full-text index: {} over ({}); matching ignores case and accents",
+            index.fts_table,
+            index.columns.join(", ")
+        );
+        if !matches!(detail, Detail::ColumnsOnly) {
+            let _ = writeln!(
+                out,
+                "  e.g. SELECT t.* FROM {table} t JOIN {fts} ON {fts}.rowid = t.rowid WHERE {fts} MATCH 'word'",
+                table = table.name,
+                fts = index.fts_table
+            );
+        }
+    }
+
+    let rows: Vec<&Vec<String>> = table.sample_rows.iter().take(detail.sample_rows()).collect();
+    if !rows.is_empty() {
+        let header: Vec<&str> = table.columns.iter().map(|c| c.name.as_str()).collect();
+        let _ = writeln!(out, "Sample rows ({}):", header.join(" | "));
+        for row in rows {
+            let cells: Vec<String> = row.iter().map(|cell| truncate(cell)).collect();
+            let _ = writeln!(out, "  {}", cells.join(" | "));
+        }
+    }
+
+    out.push('\n');
+    out
+}
+
+fn truncate(value: &str) -> String {
+    if value.chars().count() <= MAX_CELL_CHARS {
+        value.to_string()
+    } else {
+        format!("{}…", value.chars().take(MAX_CELL_CHARS).collect::<String>())
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use moka::sync::Cache;
+use sha2::{Digest, Sha256};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::services::db_loader::{DbLoader, IndexBudget};
+use crate::services::file_processor::FileVersion;
+use crate::services::llm_agent::ExecutedQuery;
+use crate::services::query_runner::ResultColumn;
+
+const SESSION_CAPACITY: u64 = 256;
+/// Session database files are named `session_<sha256 of the id>.sqlite`.
+/// Only files named like this are ever deleted from the session directory.
+const SESSION_FILE_PREFIX: &str = "session_";
+const SESSION_FILE_EXTENSION: &str = "sqlite";
+
+/// A query result kept for paging. Its rows are saved to the temporary
+/// table `table` when the first page is requested.
+#[derive(Debug, Clone)]
+pub struct StoredResult {
+    pub sql: String,
+    pub table: String,
+    pub columns: Vec<ResultColumn>,
+    pub total_row_count: Option<usize>,
+    pub stored_at: Instant,
+}
+
+/// A loaded database together with the results produced against it.
+pub struct Session {
+    pub id: String,
+    /// User whose data the session holds. Only they may use the session,
+    /// and erasing their data removes it.
+    pub user_email: String,
+    pub created_at: Instant,
+    pub db_loader: DbLoader,
+    /// Database file, removed once the session is dropped.
+    path: Option<PathBuf>,
+    /// Held while files are loaded, so concurrent requests in one chat don't
+    /// load the same file twice.
+    loading: tokio::sync::Mutex<()>,
+    results: Mutex<HashMap<String, StoredResult>>,
+    /// Contents of each loaded file, by file name.
+    files: Mutex<HashMap<String, FileVersion>>,
+}
+
+impl Session {
+    /// Waits for any load in progress and blocks others until the guard is
+    /// dropped.
+    pub async fn lock_loading(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.loading.lock().await
+    }
+
+    /// Whether a file with this name has been loaded into the session.
+    pub async fn has_file(&self, file_name: &str) -> Result<bool, AppError> {
+        Ok(self.db_loader.get_catalog().await?
+            .iter()
+            .any(|entry| entry.file_name == file_name))
+    }
+
+    /// Content hash and ETag of the file loaded under this name, if any. A
+    /// file re-uploaded under the same name with new contents has a new hash.
+    pub fn loaded_file(&self, file_name: &str) -> Option<FileVersion> {
+        self.files.lock().ok()?.get(file_name).cloned()
+    }
+
+    pub fn set_loaded_file(&self, file_name: &str, version: FileVersion) {
+        if let Ok(mut files) = self.files.lock() {
+            files.insert(file_name.to_string(), version);
+        }
+    }
+
+    pub fn remove_loaded_file(&self, file_name: &str) {
+        if let Ok(mut files) = self.files.lock() {
+            files.remove(file_name);
+        }
+    }
+
+    /// Content hashes of every file loaded into the session.
+    pub fn file_hashes(&self) -> Vec<String> {
+        self.files.lock()
+            .map(|files| files.values().map(|version| version.hash.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Keeps a handle for every query that ran successfully.
+    pub fn store_results(&self, results: &[ExecutedQuery]) {
+        let Ok(mut stored) = self.results.lock() else { return };
+        for result in results.iter().filter(|result| result.rows.error.is_none()) {
+            stored.insert(result.result_id.clone(), StoredResult {
+                sql: result.sql.clone(),
+                table: format!("result_{}", uuid::Uuid::new_v4().simple()),
+                columns: result.rows.columns.clone(),
+                total_row_count: result.rows.total_row_count,
+                stored_at: Instant::now(),
+            });
+        }
+    }
+
+    pub fn result(&self, result_id: &str) -> Option<StoredResult> {
+        self.results.lock().ok()?.get(result_id).cloned()
+    }
+
+    /// Forgets stored results whose SQL no longer compiles against the
+    /// session's database, e.g. after the tables it read were removed.
+    /// Returns how many were dropped.
+    pub async fn purge_invalid_results(&self) -> Result<usize, AppError> {
+        let stored: Vec<(String, String)> = match self.results.lock() {
+            Ok(stored) => stored.iter().map(|(id, result)| (id.clone(), result.sql.clone())).collect(),
+            Err(_) => return Ok(0),
+        };
+        if stored.is_empty() {
+            return Ok(0);
+        }
+
+        let conn = self.db_loader.get_connection().await?;
+        let invalid = conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<Vec<String>> {
+            Ok(stored.into_iter()
+                .filter(|(_, sql)| conn.prepare(sql).is_err())
+                .map(|(id, _)| id)
+                .collect())
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))?;
+
+        let tables: Vec<String> = match self.results.lock() {
+            Ok(mut stored) => invalid.iter().filter_map(|id| stored.remove(id)).map(|result| result.table).collect(),
+            Err(_) => return Ok(0),
+        };
+        let dropped = tables.len();
+        self.drop_result_tables(tables).await?;
+        Ok(dropped)
+    }
+
+    /// Forgets results stored longer than `max_age` ago, or all of them when
+    /// `max_age` is `None`, along with their saved rows. Returns how many
+    /// were dropped.
+    pub async fn purge_results(&self, max_age: Option<Duration>) -> usize {
+        let tables: Vec<String> = match self.results.lock() {
+            Ok(mut stored) => {
+                let expired: Vec<String> = stored.iter()
+                    .filter(|(_, result)| max_age.map_or(true, |max_age| result.stored_at.elapsed() > max_age))
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                expired.iter().filter_map(|id| stored.remove(id)).map(|result| result.table).collect()
+            }
+            Err(_) => return 0,
+        };
+        let dropped = tables.len();
+        if let Err(e) = self.drop_result_tables(tables).await {
+            tracing::warn!("Failed to drop stored results of session {}: {}", self.id, e);
+        }
+        dropped
+    }
+
+    /// Drops the temporary tables holding saved result rows. Results never
+    /// paged have none.
+    async fn drop_result_tables(&self, tables: Vec<String>) -> Result<(), AppError> {
+        if tables.is_empty() {
+            return Ok(());
+        }
+        let conn = self.db_loader.get_connection().await?;
+        conn.call(move |conn: &mut rusqlite::Connection| -> rusqlite::Result<()> {
+            for table in tables {
+                conn.execute(&format!("DROP TABLE IF EXISTS temp.\"{}\"", table), [])?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| AppError::DatabaseError(e.to_string()))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            // The purger may have removed it already
+            if let Err(e) = std::fs::remove_file(path).or_else(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            }) {
+                tracing::warn!("Failed to remove session database {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Sessions by id. A session, and with it its database, is dropped after
+/// sitting idle for the configured time.
+#[derive(Clone)]
+pub struct SessionStore {
+    sessions: Cache<String, Arc<Session>>,
+    /// Serializes session creation, so one id never gets two databases.
+    creating: Arc<tokio::sync::Mutex<()>>,
+    dir: Option<PathBuf>,
+    full_text_search: bool,
+    index_budget: IndexBudget,
+    relationship_budget: Duration,
+}
+
+impl SessionStore {
+    /// Session databases left in the session directory by an earlier run
+    /// belong to no session, so they are deleted here.
+    pub fn new(config: &Config) -> Self {
+        if let Some(dir) = &config.session_dir {
+            let removed = remove_session_files(dir, |_| true);
+            if removed > 0 {
+                tracing::info!("Removed {} session databases left by an earlier run", removed);
+            }
+        }
+        Self {
+            sessions: Cache::builder()
+                .max_capacity(SESSION_CAPACITY)
+                .time_to_idle(Duration::from_secs(config.session_idle_secs))
+                .build(),
+            creating: Arc::new(tokio::sync::Mutex::new(())),
+            dir: config.session_dir.clone(),
+            full_text_search: config.full_text_search,
+            index_budget: IndexBudget {
+                max_per_table: config.index_max_per_table,
+                time_budget: Duration::from_millis(config.index_time_budget_ms),
+            },
+            relationship_budget: Duration::from_millis(config.relationship_time_budget_ms),
+        }
+    }
+
+    /// Starts a session under a new random id.
+    pub async fn create(&self, user_email: &str) -> Result<Arc<Session>, AppError> {
+        self.get_or_create(&uuid::Uuid::new_v4().to_string(), user_email).await
+    }
+
+    /// The session with this id (e.g. a chat id), started with an empty
+    /// database for `user_email` if there is none. An existing session
+    /// belonging to someone else is refused.
+    pub async fn get_or_create(&self, id: &str, user_email: &str) -> Result<Arc<Session>, AppError> {
+        Ok(self.resume_or_create(id, user_email).await?.0)
+    }
+
+    /// Like `get_or_create`, also telling whether the session was just
+    /// started.
+    pub async fn resume_or_create(&self, id: &str, user_email: &str) -> Result<(Arc<Session>, bool), AppError> {
+        if let Some(session) = self.sessions.get(id) {
+            return Ok((owned_by(session, user_email)?, false));
+        }
+
+        let _creating = self.creating.lock().await;
+        if let Some(session) = self.sessions.get(id) {
+            return Ok((owned_by(session, user_email)?, false));
+        }
+
+        let path = match &self.dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir).map_err(|e| AppError::Internal(
+                    format!("Failed to create session directory {}: {}", dir.display(), e)
+                ))?;
+                Some(dir.join(session_file_name(id)))
+            }
+            None => None,
+        };
+        let db_loader = DbLoader::open(path.as_deref()).await?
+            .with_full_text_search(self.full_text_search)
+            .with_index_budget(self.index_budget)
+            .with_relationship_budget(self.relationship_budget);
+
+        let session = Arc::new(Session {
+            id: id.to_string(),
+            user_email: user_email.to_string(),
+            created_at: Instant::now(),
+            db_loader,
+            path,
+            loading: tokio::sync::Mutex::new(()),
+            results: Mutex::new(HashMap::new()),
+            files: Mutex::new(HashMap::new()),
+        });
+        self.sessions.insert(session.id.clone(), session.clone());
+        tracing::info!("Created session {}", session.id);
+        Ok((session, true))
+    }
+
+    /// The session with this id, if it belongs to `user_email`.
+    pub fn get(&self, id: &str, user_email: &str) -> Result<Arc<Session>, AppError> {
+        let session = self.sessions.get(id)
+            .ok_or_else(|| AppError::NotFound(format!("Session {} not found", id)))?;
+        owned_by(session, user_email)
+    }
+
+    /// Drops the session; its database goes once no request still uses it.
+    pub fn remove(&self, id: &str) -> Option<Arc<Session>> {
+        let session = self.sessions.remove(id);
+        // The cache keeps its own handle until its maintenance runs
+        self.sessions.run_pending_tasks();
+        if session.is_some() {
+            tracing::info!("Removed session {}", id);
+        }
+        session
+    }
+
+    /// Removes and returns the sessions matching `predicate`.
+    pub fn remove_where(&self, predicate: impl Fn(&Session) -> bool) -> Vec<Arc<Session>> {
+        let ids: Vec<Arc<String>> = self.sessions.iter()
+            .filter(|(_, session)| predicate(session))
+            .map(|(id, _)| id)
+            .collect();
+        let removed = ids.iter().filter_map(|id| self.sessions.remove(id.as_str())).collect();
+        self.sessions.run_pending_tasks();
+        removed
+    }
+
+    /// Every live session.
+    pub fn all(&self) -> Vec<Arc<Session>> {
+        self.sessions.iter().map(|(_, session)| session).collect()
+    }
+
+    /// Deletes database files in the session directory that no live session
+    /// uses, such as one a dropped session failed to remove. Returns how many
+    /// were deleted.
+    pub async fn remove_orphaned_files(&self) -> usize {
+        let Some(dir) = &self.dir else { return 0 };
+        // A session being created has its file before it is in the cache
+        let _creating = self.creating.lock().await;
+        self.sessions.run_pending_tasks();
+        let live: HashSet<PathBuf> = self.sessions.iter()
+            .filter_map(|(_, session)| session.path.clone())
+            .collect();
+        remove_session_files(dir, |path| !live.contains(path))
+    }
+}
+
+fn owned_by(session: Arc<Session>, user_email: &str) -> Result<Arc<Session>, AppError> {
+    if session.user_email == user_email {
+        Ok(session)
+    } else {
+        Err(AppError::Forbidden(format!("Session {} belongs to another user", session.id)))
+    }
+}
+
+/// Deletes the session database files in `dir` for which `remove` holds,
+/// returning how many were deleted. Other files are never touched, so a
+/// misconfigured `SESSION_DIR` doesn't lose unrelated databases.
+fn remove_session_files(dir: &Path, remove: impl Fn(&Path) -> bool) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+    let mut removed = 0;
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if !is_session_file(&path) || !remove(&path) {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::warn!("Failed to remove session database {}: {}", path.display(), e),
+        }
+    }
+    removed
+}
+
+/// File name for a session's database. Ids come from clients, so they are
+/// hashed rather than used as paths.
+fn session_file_name(id: &str) -> String {
+    format!("{}{:x}.{}", SESSION_FILE_PREFIX, Sha256::digest(id.as_bytes()), SESSION_FILE_EXTENSION)
+}
+
+/// Whether `path` is named like a file from `session_file_name`.
+fn is_session_file(path: &Path) -> bool {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else { return false };
+    file_name.strip_prefix(SESSION_FILE_PREFIX)
+        .and_then(|rest| rest.strip_suffix(SESSION_FILE_EXTENSION))
+        .and_then(|rest| rest.strip_suffix('.'))
+        .is_some_and(|hash| hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_session_files_are_removed() {
+        let dir = std::env::temp_dir().join(format!("sessions_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let session_file = dir.join(session_file_name("chat-1"));
+        let unrelated = [
+            "app.sqlite",
+            "session_notes.sqlite",
+            &format!("{:x}.sqlite", Sha256::digest(b"chat-2")),
+            &format!("{}-journal", session_file_name("chat-3")),
+        ];
+        std::fs::write(&session_file, b"").unwrap();
+        for name in unrelated {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        assert_eq!(remove_session_files(&dir, |_| true), 1);
+        assert!(!session_file.exists());
+        for name in unrelated {
+            assert!(dir.join(name).exists(), "{} was removed", name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, Error, Result};
+use crate::services::excel::utils::fold_text;
+
+const DETERMINISTIC: FunctionFlags = FunctionFlags::SQLITE_UTF8.union(FunctionFlags::SQLITE_DETERMINISTIC);
+/// Full pt-BR month names, January first.
+const MONTHS_PT: [&str; 12] = [
+    "janeiro", "fevereiro", "março", "abril", "maio", "junho",
+    "julho", "agosto", "setembro", "outubro", "novembro", "dezembro",
+];
+const MONTHS_EN: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+/// Full or abbreviated pt-BR month names, with or without the cedilla.
+static PT_MONTH: Lazy<Regex> = Lazy::new(|| Regex::new(
+    r"(?i)\b(janeiro|fevereiro|março|marco|abril|maio|junho|julho|agosto|setembro|outubro|novembro|dezembro|jan|fev|mar|abr|mai|jun|jul|ago|set|out|nov|dez)\b"
+).unwrap());
+/// The "de" in "15 de março de 2024".
+static PT_CONNECTIVE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)\s+de\s+").unwrap());
+/// Layouts tried, in order, when no explicit format is given.
+const DEFAULT_DATE_FORMATS: [&str; 8] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d",
+    "%d/%m/%Y %H:%M:%S",
+    "%d/%m/%Y",
+    "%d-%m-%Y",
+    "%d %B %Y",
+    "%d %b %Y",
+];
+
+/// Registers the statistics, regex and date functions SQLite lacks:
+///
+/// - `stddev(x)`, `variance(x)`: sample standard deviation and variance
+/// - `median(x)`, `percentile(x, p)`: `p` from 0 to 100, linearly interpolated
+/// - `x REGEXP pattern`, `regexp_replace(text, pattern, replacement)`
+/// - `unaccent(text)`: lowercased with accents stripped, for matching text
+///   regardless of case and accents
+/// - `to_date(text[, format])`: ISO date (or date-time when the format has a
+///   time), accepting pt-BR month names such as `15 de março de 2024`
+/// - `month_name(date)` in pt-BR, `week(date)` (ISO week), `quarter(date)`
+pub fn register(conn: &Connection) -> Result<()> {
+    conn.create_aggregate_function("stddev", 1, DETERMINISTIC, Moments { stddev: true })?;
+    conn.create_aggregate_function("variance", 1, DETERMINISTIC, Moments { stddev: false })?;
+    conn.create_aggregate_function("median", 1, DETERMINISTIC, Percentile { fixed: Some(50.0) })?;
+    conn.create_aggregate_function("percentile", 2, DETERMINISTIC, Percentile { fixed: None })?;
+
+    conn.create_scalar_function("regexp", 2, DETERMINISTIC, |ctx| {
+        let regex = cached_regex(ctx, 0)?;
+        Ok(text_arg(ctx, 1).map(|text| regex.is_match(&text)))
+    })?;
+    conn.create_scalar_function("regexp_replace", 3, DETERMINISTIC, |ctx| {
+        let regex = cached_regex(ctx, 1)?;
+        let replacement = text_arg(ctx, 2).unwrap_or_default();
+        Ok(text_arg(ctx, 0).map(|text| regex.replace_all(&text, replacement.as_str()).into_owned()))
+    })?;
+
+    conn.create_scalar_function("unaccent", 1, DETERMINISTIC, |ctx| {
+        Ok(text_arg(ctx, 0).map(|text| fold_text(&text)))
+    })?;
+
+    for n_arg in [1, 2] {
+        conn.create_scalar_function("to_date", n_arg, DETERMINISTIC, |ctx| {
+            let format = if ctx.len() > 1 { text_arg(ctx, 1) } else { None };
+            Ok(text_arg(ctx, 0).and_then(|text| to_date(&text, format.as_deref())))
+        })?;
+    }
+    conn.create_scalar_function("month_name", 1, DETERMINISTIC, |ctx| {
+        Ok(date_arg(ctx, 0).map(|date| MONTHS_PT[date.month0() as usize]))
+    })?;
+    conn.create_scalar_function("week", 1, DETERMINISTIC, |ctx| {
+        Ok(date_arg(ctx, 0).map(|date| date.iso_week().week()))
+    })?;
+    conn.create_scalar_function("quarter", 1, DETERMINISTIC, |ctx| {
+        Ok(date_arg(ctx, 0).map(|date| date.month0() / 3 + 1))
+    })?;
+
+    Ok(())
+}
+
+/// Running mean and sum of squared deviations (Welford), for sample
+/// variance and standard deviation.
+#[derive(Default)]
+struct Welford {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+struct Moments {
+    stddev: bool,
+}
+
+impl Aggregate<Welford, Option<f64>> for Moments {
+    fn init(&self, _: &mut Context<'_>) -> Result<Welford> {
+        Ok(Welford::default())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut Welford) -> Result<()> {
+        if let Some(x) = numeric_arg(ctx, 0) {
+            acc.count += 1;
+            let delta = x - acc.mean;
+            acc.mean += delta / acc.count as f64;
+            acc.m2 += delta * (x - acc.mean);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, acc: Option<Welford>) -> Result<Option<f64>> {
+        Ok(acc.filter(|acc| acc.count > 1).map(|acc| {
+            let variance = acc.m2 / (acc.count - 1) as f64;
+            if self.stddev { variance.sqrt() } else { variance }
+        }))
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    values: Vec<f64>,
+    percentile: Option<f64>,
+}
+
+/// `median` fixes the percentile at 50; `percentile` reads it from its
+/// second argument, which must be the same for every row.
+struct Percentile {
+    fixed: Option<f64>,
+}
+
+impl Aggregate<Samples, Option<f64>> for Percentile {
+    fn init(&self, _: &mut Context<'_>) -> Result<Samples> {
+        Ok(Samples { values: Vec::new(), percentile: self.fixed })
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut Samples) -> Result<()> {
+        if self.fixed.is_none() {
+            let p = numeric_arg(ctx, 1)
+                .filter(|p| (0.0..=100.0).contains(p))
+                .ok_or_else(|| user_error("percentile must be a number between 0 and 100"))?;
+            if acc.percentile.map_or(false, |previous| previous != p) {
+                return Err(user_error("percentile must be the same for every row"));
+            }
+            acc.percentile = Some(p);
+        }
+        if let Some(x) = numeric_arg(ctx, 0) {
+            acc.values.push(x);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, acc: Option<Samples>) -> Result<Option<f64>> {
+        let Some(Samples { mut values, percentile: Some(p) }) = acc else { return Ok(None) };
+        if values.is_empty() {
+            return Ok(None);
+        }
+        values.sort_by(f64::total_cmp);
+        let rank = p / 100.0 * (values.len() - 1) as f64;
+        let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+        Ok(Some(values[lower] + (values[upper] - values[lower]) * (rank - lower as f64)))
+    }
+}
+
+/// Numbers, and text that parses as one; anything else is skipped like NULL.
+fn numeric_arg(ctx: &Context<'_>, idx: usize) -> Option<f64> {
+    match ctx.get_raw(idx) {
+        ValueRef::Integer(i) => Some(i as f64),
+        ValueRef::Real(f) => Some(f),
+        ValueRef::Text(t) => std::str::from_utf8(t).ok()?.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn text_arg(ctx: &Context<'_>, idx: usize) -> Option<String> {
+    match ctx.get_raw(idx) {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) => Some(f.to_string()),
+        ValueRef::Text(t) | ValueRef::Blob(t) => Some(String::from_utf8_lossy(t).into_owned()),
+    }
+}
+
+fn date_arg(ctx: &Context<'_>, idx: usize) -> Option<NaiveDate> {
+    text_arg(ctx, idx)
+        .and_then(|text| parse_date_time(&text, None))
+        .map(|date_time| date_time.date())
+}
+
+/// Compiles the pattern once per statement and reuses it for every row.
+fn cached_regex(ctx: &Context<'_>, idx: usize) -> Result<Arc<Regex>> {
+    ctx.get_or_create_aux(idx as i32, |pattern| -> std::result::Result<Regex, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Regex::new(pattern.as_str()?)?)
+    })
+}
+
+fn to_date(text: &str, format: Option<&str>) -> Option<String> {
+    let parsed = parse_date_time(text, format)?;
+    let has_time = format.map_or(parsed.time() != chrono::NaiveTime::MIN, |format| {
+        ["%H", "%I", "%M", "%S", "%T", "%R"].iter().any(|spec| format.contains(spec))
+    });
+    Some(if has_time {
+        parsed.format("%Y-%m-%d %H:%M:%S").to_string()
+    } else {
+        parsed.format("%Y-%m-%d").to_string()
+    })
+}
+
+/// Parses with `format`, or with the default layouts, after turning pt-BR
+/// month names into English so `%B`/`%b` match them.
+fn parse_date_time(text: &str, format: Option<&str>) -> Option<NaiveDateTime> {
+    let text = english_month_names(text.trim());
+    let parse = |format: &str| {
+        NaiveDateTime::parse_from_str(&text, format)
+            .ok()
+            .or_else(|| NaiveDate::parse_from_str(&text, format).ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+    };
+    match format {
+        Some(format) => parse(&english_month_names(format)),
+        None => DEFAULT_DATE_FORMATS.iter().find_map(|format| parse(format)),
+    }
+}
+
+fn english_month_names(text: &str) -> String {
+    let text = PT_CONNECTIVE.replace_all(text, " ");
+    PT_MONTH.replace_all(&text, |caps: &regex::Captures<'_>| {
+        let prefix: String = caps[0].to_lowercase().chars().take(3).collect();
+        let month = MONTHS_PT.iter().position(|pt| pt.starts_with(&prefix));
+        // `%b` only accepts abbreviations, while `%B` accepts both
+        match month {
+            Some(m) if caps[0].chars().count() == 3 => MONTHS_EN[m][..3].to_string(),
+            Some(m) => MONTHS_EN[m].to_string(),
+            None => caps[0].to_string(),
+        }
+    })
+    .into_owned()
+}
+
+fn user_error(message: &str) -> Error {
+    Error::UserFunctionError(message.into())
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use rusqlite::{Connection, ErrorCode, Statement};
+
+/// A common table expression's name: `name [(columns)] AS [[NOT] MATERIALIZED] (`.
+static CTE_NAME_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)(?:"([^"]+)"|\[([^\]]+)\]|`([^`]+)`|\b([A-Za-z_][A-Za-z0-9_]*))\s*(?:\([^()]*\)\s*)?AS\s+(?:NOT\s+)?(?:MATERIALIZED\s*)?\("#)
+        .expect("valid CTE name regex")
+});
+
+/// Table-valued functions that only read their arguments.
+const READABLE_FUNCTIONS: [&str; 2] = ["json_each", "json_tree"];
+
+#[derive(Debug)]
+pub enum GuardError {
+    /// The statement does something other than read catalogued tables.
+    Rejected(String),
+    Sqlite(rusqlite::Error),
+}
+
+/// Restricts LLM-generated SQL to single read-only statements that only
+/// read catalogued tables. Writes, schema changes, PRAGMA, ATTACH and
+/// `load_extension` are refused by the authorizer while the statement is
+/// being prepared, so nothing runs. The statement's text is not inspected,
+/// so leading comments and parentheses are fine.
+#[derive(Debug, Clone)]
+pub struct ReadOnlyGuard {
+    readable_tables: Arc<HashSet<String>>,
+}
+
+impl ReadOnlyGuard {
+    pub fn new(readable_tables: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            readable_tables: Arc::new(readable_tables.into_iter().map(|t| t.to_lowercase()).collect()),
+        }
+    }
+
+    /// Prepares `sql` with the authorizer installed. The authorizer is
+    /// removed again before returning, so the loader's own statements on the
+    /// same connection are unaffected.
+    pub fn prepare<'c>(&self, conn: &'c Connection, sql: &str) -> Result<Statement<'c>, GuardError> {
+        let schema_tables = schema_tables(conn).map_err(GuardError::Sqlite)?;
+        let denial = Arc::new(Mutex::new(None));
+        conn.authorizer(Some(authorizer(self.readable_tables.clone(), schema_tables, cte_names(sql), denial.clone())));
+        let prepared = conn.prepare(sql);
+        conn.authorizer(None::<fn(AuthContext<'_>) -> Authorization>);
+
+        if let Some(reason) = denial.lock().ok().and_then(|mut reason| reason.take()) {
+            return Err(GuardError::Rejected(reason));
+        }
+        match prepared {
+            Ok(stmt) if !stmt.readonly() => Err(GuardError::Rejected("the query would modify the database".to_string())),
+            Ok(stmt) if stmt.is_explain() != 0 => Err(GuardError::Rejected("EXPLAIN is not allowed".to_string())),
+            Ok(stmt) if has_trailing_statement(sql, &stmt) => {
+                Err(GuardError::Rejected("only one statement per query is allowed".to_string()))
+            }
+            Ok(stmt) => Ok(stmt),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::AuthorizationForStatementDenied => {
+                Err(GuardError::Rejected("not authorized".to_string()))
+            }
+            Err(e) => Err(GuardError::Sqlite(e)),
+        }
+    }
+}
+
+/// SQLite prepares only the first statement of `sql`; anything after it
+/// other than whitespace or semicolons would be silently dropped.
+fn has_trailing_statement(sql: &str, stmt: &Statement<'_>) -> bool {
+    stmt.expanded_sql()
+        .and_then(|first| sql.strip_prefix(first.as_str()).map(str::to_string))
+        .map_or(false, |tail| tail.chars().any(|c| !c.is_whitespace() && c != ';'))
+}
+
+/// Lowercased names of every table and view in the database, including the
+/// schema tables themselves.
+fn schema_tables(conn: &Connection) -> rusqlite::Result<HashSet<String>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT lower(name) FROM sqlite_schema UNION ALL SELECT lower(name) FROM sqlite_temp_schema",
+    )?;
+    let mut tables = stmt.query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<HashSet<_>>>()?;
+    tables.extend(["sqlite_schema", "sqlite_master", "sqlite_temp_schema", "sqlite_temp_master"].map(String::from));
+    Ok(tables)
+}
+
+/// Lowercased names of the common table expressions `sql` declares.
+fn cte_names(sql: &str) -> HashSet<String> {
+    CTE_NAME_RE.captures_iter(sql)
+        .filter_map(|caps| (1..=4).find_map(|i| caps.get(i)))
+        .map(|name| name.as_str().to_lowercase())
+        .collect()
+}
+
+fn authorizer(
+    readable_tables: Arc<HashSet<String>>,
+    schema_tables: HashSet<String>,
+    cte_names: HashSet<String>,
+    denial: Arc<Mutex<Option<String>>>,
+) -> impl for<'r> FnMut(AuthContext<'r>) -> Authorization + Send + std::panic::RefUnwindSafe + 'static {
+    move |ctx: AuthContext<'_>| {
+        let verdict = match ctx.action {
+            AuthAction::Select | AuthAction::Recursive => Ok(()),
+            AuthAction::Read { table_name, .. } if readable_tables.contains(&table_name.to_lowercase()) => Ok(()),
+            // `count(*)` over a CTE reports a read of the CTE name itself; the
+            // tables it draws from are authorized on their own
+            AuthAction::Read { table_name, .. }
+                if cte_names.contains(&table_name.to_lowercase())
+                    && !schema_tables.contains(&table_name.to_lowercase()) => Ok(()),
+            AuthAction::Read { table_name, .. }
+                if READABLE_FUNCTIONS.iter().any(|f| f.eq_ignore_ascii_case(table_name)) => Ok(()),
+            AuthAction::Read { table_name, .. } => Err(format!("table {} is not available for queries", table_name)),
+            AuthAction::Function { function_name } if function_name.eq_ignore_ascii_case("load_extension") => {
+                Err("load_extension is not allowed".to_string())
+            }
+            AuthAction::Function { .. } => Ok(()),
+            AuthAction::Pragma { pragma_name, .. } => Err(format!("PRAGMA {} is not allowed", pragma_name)),
+            AuthAction::Insert { table_name }
+            | AuthAction::Delete { table_name }
+            | AuthAction::Update { table_name, .. } => Err(format!("modifying table {} is not allowed", table_name)),
+            action => Err(format!("{:?} is not allowed; only SELECT queries can be run", action)),
+        };
+
+        match verdict {
+            Ok(()) => Authorization::Allow,
+            Err(reason) => {
+                if let Ok(mut denial) = denial.lock() {
+                    denial.get_or_insert(reason);
+                }
+                Authorization::Deny
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE vendas (estado TEXT, valor REAL);
+             INSERT INTO vendas VALUES ('SP', 10), ('RJ', 20);
+             CREATE TABLE _column_map (table_name TEXT, column_name TEXT);"
+        ).unwrap();
+        conn
+    }
+
+    fn check(sql: &str) -> Result<(), String> {
+        let conn = connection();
+        let guard = ReadOnlyGuard::new(["vendas".to_string()]);
+        let checked = match guard.prepare(&conn, sql) {
+            Ok(_) => Ok(()),
+            Err(GuardError::Rejected(reason)) => Err(reason),
+            Err(GuardError::Sqlite(e)) => Err(e.to_string()),
+        };
+        checked
+    }
+
+    #[test]
+    fn allows_reads_of_catalogued_tables() {
+        for sql in [
+            "SELECT * FROM vendas",
+            "select estado, SUM(valor) FROM vendas GROUP BY estado;",
+            "-- total by state\nSELECT estado FROM vendas",
+            "/* leading comment */ SELECT estado FROM vendas",
+            "  \n\tSELECT 1",
+            "WITH sp AS (SELECT * FROM vendas WHERE estado = 'SP') SELECT COUNT(*) FROM sp",
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3) SELECT i FROM n",
+            "SELECT value FROM json_each('[1, 2]')",
+            "VALUES (1), (2)",
+        ] {
+            assert_eq!(check(sql), Ok(()), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn denies_writes_and_side_effects() {
+        for sql in [
+            "INSERT INTO vendas VALUES ('MG', 1)",
+            "UPDATE vendas SET valor = 0",
+            "DELETE FROM vendas",
+            "DROP TABLE vendas",
+            "CREATE TABLE x (a)",
+            "/* looks harmless */ DELETE FROM vendas",
+            "WITH x AS (SELECT 'MG', 1) INSERT INTO vendas SELECT * FROM x",
+            "PRAGMA journal_mode = DELETE",
+            "PRAGMA writable_schema = ON",
+            "PRAGMA table_info(vendas)",
+            "ATTACH DATABASE ':memory:' AS other",
+            "DETACH DATABASE main",
+            "SELECT load_extension('evil')",
+            "BEGIN",
+            "VACUUM",
+            "EXPLAIN SELECT * FROM vendas",
+            "SELECT 1; DROP TABLE vendas",
+            "-- one\nSELECT 1; DELETE FROM vendas",
+        ] {
+            assert!(check(sql).is_err(), "{} was allowed", sql);
+        }
+    }
+
+    #[test]
+    fn denies_reads_of_other_tables() {
+        for sql in [
+            "SELECT * FROM _column_map",
+            "SELECT * FROM sqlite_master",
+            "SELECT * FROM pragma_table_info('vendas')",
+            "WITH x AS (SELECT * FROM _column_map) SELECT * FROM x",
+            // A CTE named after a real table doesn't make that table readable
+            "WITH _column_map AS (SELECT 1) SELECT * FROM main._column_map",
+        ] {
+            assert!(check(sql).is_err(), "{} was allowed", sql);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::services::excel::utils::to_ascii_identifier;
+
+/// Minimum similarity (1.0 = identical) for a fuzzy match to replace a literal.
+const MIN_SIMILARITY: f64 = 0.75;
+
+/// Words that end a `FROM`/`JOIN` table reference instead of naming an alias.
+const CLAUSE_KEYWORDS: [&str; 20] = [
+    "where", "on", "using", "join", "inner", "left", "right", "full", "cross", "natural",
+    "group", "order", "limit", "having", "union", "except", "intersect", "window", "outer", "as",
+];
+
+/// A string literal in generated SQL that was rewritten to a real column value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiteralCorrection {
+    pub column: String,
+    pub original: String,
+    pub corrected: String,
+}
+
+/// Distinct values of low-cardinality text columns, by table and column.
+#[derive(Debug, Default, Clone)]
+pub struct ValueDictionary {
+    tables: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+impl ValueDictionary {
+    pub fn insert(&mut self, table: &str, column: &str, value: String) {
+        let values = self.tables
+            .entry(table.to_lowercase())
+            .or_default()
+            .entry(column.to_lowercase())
+            .or_default();
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Rewrites literals a column must equal (`=`, `==`, `IN (...)`) when
+    /// they don't match any known value but have an unambiguous close match
+    /// (different casing or accents, a typo, or initials such as
+    /// "São Paulo" → "SP"). Literals a column must differ from (`!=`, `<>`,
+    /// `NOT IN`, `NOT col = ...`) are left alone, since correcting them would
+    /// change what the query excludes.
+    pub fn correct_literals(&self, sql: &str) -> (String, Vec<LiteralCorrection>) {
+        let tokens = tokenize(sql);
+        let tables = referenced_tables(&tokens);
+
+        let mut corrected_sql = sql.to_string();
+        let mut corrections = Vec::new();
+        // Replace from the end so earlier spans stay valid
+        for (column, literal) in compared_literals(&tokens).into_iter().rev() {
+            let Token::Literal { start, end, value } = literal else { continue };
+            let values = self.values_for(&column, &tables);
+            if values.is_empty() || values.iter().any(|v| *v == value) {
+                continue;
+            }
+            let values: Vec<String> = values.into_iter().cloned().collect();
+            if let Some(best) = closest_value(value, &values) {
+                corrected_sql.replace_range(*start..*end, &best.replace('\'', "''"));
+                corrections.push(LiteralCorrection {
+                    column: column.name.clone(),
+                    original: value.clone(),
+                    corrected: best.to_string(),
+                });
+            }
+        }
+
+        corrections.reverse();
+        (corrected_sql, corrections)
+    }
+
+    /// Known values of the column: from the table its qualifier names, or
+    /// else from the tables the query reads that have such a column, or else
+    /// from the only table with that column.
+    fn values_for(&self, column: &ColumnRef, tables: &HashMap<String, String>) -> Vec<&String> {
+        let lookup = |table: &str| self.tables.get(table).and_then(|columns| columns.get(&column.name));
+
+        if let Some(qualifier) = &column.qualifier {
+            let table = tables.get(qualifier).map_or(qualifier.as_str(), String::as_str);
+            return lookup(table).map(|values| values.iter().collect()).unwrap_or_default();
+        }
+
+        let mut read: Vec<&str> = tables.values().map(String::as_str).collect();
+        read.sort_unstable();
+        read.dedup();
+        let mut candidates: Vec<&Vec<String>> = read.into_iter().filter_map(lookup).collect();
+        if candidates.is_empty() {
+            candidates = self.tables.values().filter_map(|columns| columns.get(&column.name)).collect();
+            if candidates.len() > 1 {
+                return Vec::new();
+            }
+        }
+
+        let mut values: Vec<&String> = Vec::new();
+        for value in candidates.into_iter().flatten() {
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        values
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// Bare identifier or keyword.
+    Word(String),
+    /// `"quoted identifier"`, unescaped.
+    Quoted(String),
+    /// `'string literal'`: byte span of its contents in the SQL, and its
+    /// unescaped value.
+    Literal { start: usize, end: usize, value: String },
+    Symbol(String),
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self, Token::Symbol(s) if s == symbol)
+    }
+
+    /// Lowercased identifier, for words and quoted identifiers.
+    fn identifier(&self) -> Option<String> {
+        match self {
+            Token::Word(word) | Token::Quoted(word) => Some(word.to_lowercase()),
+            _ => None,
+        }
+    }
+}
+
+/// Splits SQL into words, quoted identifiers, string literals and symbols,
+/// skipping whitespace and comments.
+fn tokenize(sql: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((idx, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        match c {
+            c if c.is_whitespace() => {}
+            '-' if next == Some('-') => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            '/' if next == Some('*') => {
+                chars.next();
+                let mut prev = ' ';
+                for (_, c) in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '\'' | '"' => {
+                let start = idx + 1;
+                let mut end = sql.len();
+                while let Some((i, ch)) = chars.next() {
+                    if ch == c {
+                        // A doubled quote is an escaped quote
+                        if chars.next_if(|&(_, next)| next == c).is_none() {
+                            end = i;
+                            break;
+                        }
+                    }
+                }
+                let doubled = if c == '\'' { "''" } else { "\"\"" };
+                let value = sql[start..end].replace(doubled, &c.to_string());
+                tokens.push(if c == '\'' {
+                    Token::Literal { start, end, value }
+                } else {
+                    Token::Quoted(value)
+                });
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut end = idx + c.len_utf8();
+                while let Some((i, ch)) = chars.next_if(|&(_, ch)| ch.is_alphanumeric() || ch == '_') {
+                    end = i + ch.len_utf8();
+                }
+                tokens.push(Token::Word(sql[idx..end].to_string()));
+            }
+            _ => {
+                let pair: String = [c].into_iter().chain(next).collect();
+                if ["==", "!=", "<>", "<=", ">=", "||"].contains(&pair.as_str()) {
+                    chars.next();
+                    tokens.push(Token::Symbol(pair));
+                } else {
+                    tokens.push(Token::Symbol(c.to_string()));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+/// A possibly qualified column reference, lowercased.
+#[derive(Debug)]
+struct ColumnRef {
+    qualifier: Option<String>,
+    name: String,
+}
+
+/// Reads the column reference ending at `tokens[end]`, and whether it is
+/// negated by a preceding `NOT`.
+fn column_before(tokens: &[Token], end: usize) -> Option<(ColumnRef, bool)> {
+    let name = tokens.get(end)?.identifier()?;
+    let (qualifier, first) = match end.checked_sub(2).map(|i| (&tokens[i], &tokens[i + 1])) {
+        Some((qualifier, dot)) if dot.is_symbol(".") => (Some(qualifier.identifier()?), end - 2),
+        _ => (None, end),
+    };
+    let negated = first.checked_sub(1).is_some_and(|i| tokens[i].is_keyword("not"));
+    Some((ColumnRef { qualifier, name }, negated))
+}
+
+/// Literals a column is required to equal, with that column.
+fn compared_literals(tokens: &[Token]) -> Vec<(ColumnRef, &Token)> {
+    let mut literals = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        // column = 'value'
+        if matches!(token, Token::Literal { .. }) && i >= 2
+            && (tokens[i - 1].is_symbol("=") || tokens[i - 1].is_symbol("=="))
+        {
+            if let Some((column, false)) = column_before(tokens, i - 2) {
+                literals.push((column, token));
+            }
+        }
+
+        // column IN ('a', 'b'), but not NOT IN or IN (SELECT ...)
+        if token.is_keyword("in") && i >= 1 && tokens.get(i + 1).is_some_and(|t| t.is_symbol("(")) {
+            if tokens[i - 1].is_keyword("not") {
+                continue;
+            }
+            let Some((column, false)) = column_before(tokens, i - 1) else { continue };
+            let items: Vec<&Token> = tokens[i + 2..].iter()
+                .take_while(|t| !t.is_symbol(")"))
+                .collect();
+            if items.iter().all(|t| matches!(t, Token::Literal { .. }) || t.is_symbol(",")) {
+                let qualifier = column.qualifier;
+                for item in items.into_iter().filter(|t| matches!(t, Token::Literal { .. })) {
+                    literals.push((ColumnRef { qualifier: qualifier.clone(), name: column.name.clone() }, item));
+                }
+            }
+        }
+    }
+    literals.sort_by_key(|(_, literal)| match literal {
+        Token::Literal { start, .. } => *start,
+        _ => 0,
+    });
+    literals
+}
+
+/// Tables named after `FROM`/`JOIN` (and comma joins), by alias and by name.
+fn referenced_tables(tokens: &[Token]) -> HashMap<String, String> {
+    let mut tables = HashMap::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if !(tokens[i].is_keyword("from") || tokens[i].is_keyword("join")) {
+            i += 1;
+            continue;
+        }
+        i += 1;
+        while let Some(table) = tokens.get(i).and_then(Token::identifier) {
+            i += 1;
+            tables.insert(table.clone(), table.clone());
+            if tokens.get(i).is_some_and(|t| t.is_keyword("as")) {
+                i += 1;
+            }
+            if let Some(alias) = tokens.get(i).and_then(Token::identifier) {
+                if !CLAUSE_KEYWORDS.contains(&alias.as_str()) {
+                    tables.insert(alias, table);
+                    i += 1;
+                }
+            }
+            if tokens.get(i).is_some_and(|t| t.is_symbol(",")) {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+    }
+    tables
+}
+
+fn closest_value<'a>(literal: &str, values: &'a [String]) -> Option<&'a str> {
+    let key = to_ascii_identifier(literal);
+    if key.is_empty() {
+        return None;
+    }
+
+    // Same value up to casing, accents and punctuation
+    if let Some(value) = values.iter().find(|v| to_ascii_identifier(v) == key) {
+        return Some(value);
+    }
+
+    // Initials of a multi-word literal, e.g. "Rio de Janeiro" → "RJ"
+    let words: Vec<&str> = key.split('_').filter(|w| w.len() > 2).collect();
+    if words.len() > 1 {
+        let initials: String = words.iter().filter_map(|w| w.chars().next()).collect();
+        let matches: Vec<&String> = values.iter()
+            .filter(|v| to_ascii_identifier(v) == initials)
+            .collect();
+        if let [value] = matches.as_slice() {
+            return Some(value);
+        }
+    }
+
+    let mut scored: Vec<(f64, &String)> = values.iter()
+        .map(|v| (similarity(&key, &to_ascii_identifier(v)), v))
+        .filter(|(score, _)| *score >= MIN_SIMILARITY)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    match scored.as_slice() {
+        [(best, value), (second, _), ..] if best > second => Some(value),
+        [(_, value)] => Some(value),
+        _ => None,
+    }
+}
+
+/// Normalized Levenshtein similarity in `[0, 1]`.
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    1.0 - prev[b.len()] as f64 / longest as f64
+}